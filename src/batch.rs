@@ -0,0 +1,221 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Parallel decoding of many messages at once (requires the `rayon` feature).
+//!
+//! Each buffer holds one complete message and any bytes after it are an error. Buffers are
+//! decoded on the rayon thread pool and the results are returned in the same order as the
+//! input, with a failure in one buffer reported in its own slot without affecting the others.
+
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+
+use crate::error::{Error, Result};
+use crate::value::layout::Layout;
+use crate::value::{decode_value, DecodePlan, Value};
+use crate::Deserializer;
+
+/// Decode each buffer as a `T`
+pub fn from_buffers<T, B>(buffers: &[B]) -> Vec<Result<T>>
+where
+    T: DeserializeOwned + Send,
+    B: AsRef<[u8]> + Sync,
+{
+    buffers
+        .par_iter()
+        .map(|buf| {
+            let buf = buf.as_ref();
+            whole_message(buf.len(), Deserializer::from_reader(buf))
+        })
+        .collect()
+}
+
+/// Decode each buffer as a `T` using a single shared layout
+pub fn from_buffers_with_layout_as<T, B>(buffers: &[B], layout: &Layout) -> Vec<Result<T>>
+where
+    T: DeserializeOwned + Send,
+    B: AsRef<[u8]> + Sync,
+{
+    buffers
+        .par_iter()
+        .map(|buf| {
+            let buf = buf.as_ref();
+            whole_message(
+                buf.len(),
+                Deserializer::from_reader_with_layout_ref(buf, layout),
+            )
+        })
+        .collect()
+}
+
+/// Decode each buffer as a loosely typed Value using a single shared layout.
+/// The layout is compiled into a `DecodePlan` once and shared by all of the workers.
+/// Fails without decoding anything if the layout cannot be compiled.
+/// A buffer that fails to decode is reported as an `Error::LayoutDecode` as by `decode_value`
+pub fn from_buffers_with_layout<B>(buffers: &[B], layout: &Layout) -> Result<Vec<Result<Value>>>
+where
+    B: AsRef<[u8]> + Sync,
+{
+    let plan = DecodePlan::compile(&layout.bin_prot_rule)?;
+    Ok(buffers
+        .par_iter()
+        .map(|buf| {
+            let buf = buf.as_ref();
+            let mut rdr = buf;
+            match plan.decode(&mut rdr) {
+                Ok(value) => trailing_bytes(buf.len(), rdr.len()).map(|()| value),
+                // the plan does not keep track of where it is so decode again to find out
+                Err(e) => Err(decode_value(buf, &layout.bin_prot_rule).err().unwrap_or(e)),
+            }
+        })
+        .collect())
+}
+
+// Deserializes the message and checks that nothing is left over after it
+fn whole_message<T: DeserializeOwned>(len: usize, mut de: Deserializer<&[u8]>) -> Result<T> {
+    let value = T::deserialize(&mut de)?;
+    // the deserializer buffers its reader so some of what is left may be in the buffer
+    let remaining = de.rdr.buffer().len() + de.rdr.get_ref().len();
+    trailing_bytes(len, remaining).map(|()| value)
+}
+
+fn trailing_bytes(len: usize, remaining: usize) -> Result<()> {
+    match remaining {
+        0 => Ok(()),
+        count => Err(Error::TrailingBytes {
+            offset: len - count,
+            count,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    const LAYOUT: &str = r#"
+    {
+      "layout_loc": "",
+      "version_opt": null,
+      "type_decl": "",
+      "bin_io_derived": false,
+      "bin_prot_rule": ["Record", [
+        { "field_name": "a", "field_rule": ["Int"] },
+        { "field_name": "b", "field_rule": ["Option", ["Bool"]] }
+      ]]
+    }
+    "#;
+
+    fn buffers() -> Vec<Vec<u8>> {
+        (0..100u8)
+            .map(|i| match i {
+                13 => vec![i, 0x01, 0x07], // invalid bool
+                _ => vec![i, 0x01, i % 2],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_typed_values_in_order() {
+        let results: Vec<Result<(u8, Option<bool>)>> = from_buffers(&buffers());
+        assert_eq!(results.len(), 100);
+        for (i, result) in results.into_iter().enumerate() {
+            match i {
+                13 => assert!(result.is_err()),
+                _ => assert_eq!(result.unwrap(), (i as u8, Some(i % 2 == 1))),
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_values_with_shared_layout() {
+        let layout: Layout = serde_json::from_str(LAYOUT).unwrap();
+        let results = from_buffers_with_layout(&buffers(), &layout).unwrap();
+        assert_eq!(results.len(), 100);
+        for (i, result) in results.into_iter().enumerate() {
+            match i {
+                13 => match result {
+                    Err(Error::LayoutDecode {
+                        path,
+                        offset,
+                        error,
+                        ..
+                    }) => {
+                        assert_eq!(path, "/b");
+                        assert_eq!(offset, 2);
+                        assert!(matches!(*error, Error::InvalidByte { byte: 0x07, .. }));
+                    }
+                    r => panic!("unexpected result {:?}", r),
+                },
+                _ => {
+                    let value = result.unwrap();
+                    assert_eq!(value["a"], Value::Int(i as i64));
+                    assert_eq!(
+                        value["b"],
+                        Value::Option(Some(Box::new(Value::Bool(i % 2 == 1))))
+                    );
+                }
+            }
+        }
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Message {
+        a: i64,
+        b: Option<bool>,
+    }
+
+    #[test]
+    fn decodes_typed_values_with_shared_layout() {
+        let layout: Layout = serde_json::from_str(LAYOUT).unwrap();
+        let results: Vec<Result<Message>> = from_buffers_with_layout_as(&buffers(), &layout);
+        assert_eq!(results.len(), 100);
+        for (i, result) in results.into_iter().enumerate() {
+            match i {
+                13 => assert!(result.is_err()),
+                _ => assert_eq!(
+                    result.unwrap(),
+                    Message {
+                        a: i as i64,
+                        b: Some(i % 2 == 1)
+                    }
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let typed: Vec<Result<bool>> = from_buffers(&[vec![0x01], vec![0x01, 0xff, 0xff]]);
+        assert!(typed[0].is_ok());
+        assert!(matches!(
+            typed[1],
+            Err(Error::TrailingBytes {
+                offset: 1,
+                count: 2
+            })
+        ));
+
+        let layout: Layout = serde_json::from_str(LAYOUT).unwrap();
+        let buffers = [vec![0x05, 0x00], vec![0x05, 0x00, 0xff]];
+        let values = from_buffers_with_layout(&buffers, &layout).unwrap();
+        assert!(values[0].is_ok());
+        assert!(matches!(
+            values[1],
+            Err(Error::TrailingBytes {
+                offset: 2,
+                count: 1
+            })
+        ));
+        let messages: Vec<Result<Message>> = from_buffers_with_layout_as(&buffers, &layout);
+        assert!(messages[0].is_ok());
+        assert!(matches!(
+            messages[1],
+            Err(Error::TrailingBytes {
+                offset: 2,
+                count: 1
+            })
+        ));
+    }
+}
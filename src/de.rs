@@ -1,7 +1,7 @@
 use std::io::{BufReader, Read};
 
 use crate::error::{Error, Result};
-use crate::value::layout::{BinProtRule, BinProtRuleIterator};
+use crate::value::layout::{BinProtRule, Layout, ModulePath};
 use crate::ReadBinProtExt;
 use byteorder::{LittleEndian, ReadBytesExt};
 use serde::de::{self, value::U8Deserializer, EnumAccess, IntoDeserializer, Visitor};
@@ -9,23 +9,41 @@ use serde::Deserialize;
 
 pub struct Deserializer<R: Read> {
     pub rdr: BufReader<R>,
-    pub layout_iter: Option<BinProtRuleIterator>,
+    pub layout: Option<BinProtRule>,
+    // module path of the reference enclosing the layout, if it is part of a larger one
+    pub(crate) module_path: ModulePath,
 }
 
 impl<R: Read> Deserializer<R> {
     pub fn from_reader(rdr: R) -> Self {
         Self {
             rdr: BufReader::new(rdr),
-            layout_iter: None,
+            layout: None,
+            module_path: ModulePath::default(),
         }
     }
 
     pub fn from_reader_with_layout(rdr: R, layout: BinProtRule) -> Self {
         Self {
             rdr: BufReader::new(rdr),
-            layout_iter: Some(layout.into_branching_iter()),
+            layout: Some(layout),
+            module_path: ModulePath::default(),
         }
     }
+
+    // for a layout taken from inside a reference, so that custom rules it contains are
+    // decoded as they would be within the whole layout
+    pub(crate) fn with_module_path(mut self, module_path: ModulePath) -> Self {
+        self.module_path = module_path;
+        self
+    }
+
+    /// Borrows the layout rather than consuming it so one layout (e.g. in an `Arc`)
+    /// can be used to decode many messages. Rule children are shared so this does not
+    /// copy the rule tree
+    pub fn from_reader_with_layout_ref(rdr: R, layout: &Layout) -> Self {
+        Self::from_reader_with_layout(rdr, layout.bin_prot_rule.clone())
+    }
 }
 
 pub fn from_reader<'de, R: Read, T: Deserialize<'de>>(rdr: R) -> Result<T> {
@@ -34,7 +52,7 @@ pub fn from_reader<'de, R: Read, T: Deserialize<'de>>(rdr: R) -> Result<T> {
     Ok(value)
 }
 
-impl<'de, 'a, R: Read> de::Deserializer<'de> for &'a mut Deserializer<R> {
+impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     type Error = Error;
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
@@ -144,18 +162,19 @@ impl<'de, 'a, R: Read> de::Deserializer<'de> for &'a mut Deserializer<R> {
         visitor.visit_string(self.rdr.bin_read_string()?)
     }
 
-    fn deserialize_bytes<V>(self, _visitor: V) -> Result<V::Value>
+    // Bytes have the same encoding as a string but need not be valid utf-8
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        Err(Error::WontImplement)
+        self.deserialize_byte_buf(visitor)
     }
 
-    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        Err(Error::WontImplement)
+        visitor.visit_byte_buf(self.rdr.bin_read_bytes()?)
     }
 
     // An absent optional is represented as 0x00
@@ -239,7 +258,7 @@ impl<'de, 'a, R: Read> de::Deserializer<'de> for &'a mut Deserializer<R> {
         // we can't know the field names (and don't need to) if we are deserializing in
         // stronly typed mode. To make everything work just add some dummy field names
         let len: usize = self.rdr.bin_read_nat0()?;
-        let dummy_fields = std::iter::repeat("".to_string()).take(len).collect();
+        let dummy_fields = std::iter::repeat_n("".to_string(), len).collect();
         visitor.visit_map(MapAccess::new(self, dummy_fields))
     }
 
@@ -365,7 +384,7 @@ pub struct Enum<'a, R: Read> {
     index: u8,
 }
 
-impl<'a, 'de, R: Read> Enum<'a, R> {
+impl<'a, R: Read> Enum<'a, R> {
     pub fn new(de: &'a mut Deserializer<R>, index: u8) -> Self {
         Enum { de, index }
     }
@@ -381,7 +400,7 @@ impl<'de, 'a, R: Read> EnumAccess<'de> for Enum<'a, R> {
     where
         V: de::DeserializeSeed<'de>,
     {
-        let de: U8Deserializer<Self::Error> = (self.index as u8).into_deserializer();
+        let de: U8Deserializer<Self::Error> = self.index.into_deserializer();
         let v = seed.deserialize(de)?;
         Ok((v, self))
     }
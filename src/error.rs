@@ -7,6 +7,8 @@ use std::result;
 use serde::{de, ser};
 use thiserror::Error;
 
+use crate::value::Value;
+
 /// A result whose error type is `Error`.
 pub type Result<A> = result::Result<A, Error>;
 
@@ -20,6 +22,10 @@ pub enum Error {
     #[error("end of stream")]
     EndOfStream,
 
+    /// A buffer that should hold exactly one message has bytes after the end of it
+    #[error("{count} bytes left over after the message ending at byte offset {offset}")]
+    TrailingBytes { offset: usize, count: usize },
+
     /// Size not given when serializing a sequence
     #[error("Attempting to serialize a sequence but size not provided")]
     SeqSizeNotProvided,
@@ -50,6 +56,59 @@ pub enum Error {
     #[error("Attempted to deserialize an integer into a desgination type that is too small")]
     DestinationIntegerOverflow,
 
+    /// A Value variant could not be converted into the requested Rust type
+    #[error("Cannot convert value {value} into a {dtype}")]
+    InvalidValueConversion { value: String, dtype: String },
+
+    /// A record field required by the layout is missing from the JSON input
+    #[error("Missing field {field} at {path:?}")]
+    MissingField { field: String, path: String },
+
+    /// The input has a record field that is not in the layout
+    #[error("Unknown field {field} at {path:?}")]
+    UnknownField { field: String, path: String },
+
+    /// The JSON input has a different type to the one required by the layout
+    #[error("Expected {expected} at {path:?}, found: {found}")]
+    InvalidJsonType {
+        expected: String,
+        found: String,
+        path: String,
+    },
+
+    /// A constructor name is not one of the summands in the layout
+    #[error("Unknown constructor {name} at {path:?}")]
+    UnknownConstructor { name: String, path: String },
+
+    /// The text could not be parsed as an s-expression
+    #[error("Invalid s-expression: {message}, at position: {pos}")]
+    SexpParse { message: String, pos: usize },
+
+    /// The s-expression has a different shape to the one required by the layout
+    #[error("Expected {expected} at {path:?}, found: {found}")]
+    InvalidSexp {
+        expected: String,
+        found: String,
+        path: String,
+    },
+
+    /// An Error.t received from OCaml, e.g. in an Or_error.t
+    #[error("OCaml error: {message}")]
+    OcamlError { message: String },
+
+    /// Decoding with a layout failed. `path` is the node being decoded, `offset` where the read
+    /// that failed started and `partial` what was decoded before the failure, which is only
+    /// kept by `value::decode_value`
+    #[error("Failed decoding {expected} at {path:?}, byte offset {offset}: {error}")]
+    LayoutDecode {
+        path: String,
+        offset: usize,
+        expected: String,
+        #[source]
+        error: Box<Error>,
+        partial: Option<Box<Value>>,
+    },
+
     /// Error occurred at a given position (recursive variant) [not currently used]
     #[error("Error: {error}, at position: {pos}")]
     ErrorAt {
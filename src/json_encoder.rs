@@ -0,0 +1,384 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Encoding of JSON into bin_prot using a layout.
+//!
+//! The layout is walked depth first and the matching part of the JSON is read at each node.
+//! Both the yojson style (constructors as `["Ctor", args...]`) and the plain serde style
+//! (constructors as `"Ctor"` or `{"Ctor": payload}`) are accepted. Records are objects and
+//! options are `null` or the value in both styles, and strings that are not valid utf-8 are an
+//! array of their bytes (see value/json.rs).
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::io::Write;
+
+use serde_json::Value as JsonValue;
+
+use crate::error::{Error, Result};
+use crate::value::layout::{
+    custom_vector, summand_index, BinProtRule, ModulePath, RuleRef, Summand, Walker,
+};
+use crate::WriteBinProtExt;
+
+/// Encode a JSON value as bin_prot following the given layout rule
+pub fn json_to_bin_prot<W: Write>(
+    json: &JsonValue,
+    rule: &BinProtRule,
+    writer: &mut W,
+) -> Result<()> {
+    let mut encoder = JsonEncoder {
+        writer,
+        path: Vec::new(),
+        module_path: ModulePath::default(),
+    };
+    encoder.encode(json, rule)
+}
+
+struct JsonEncoder<'a, W> {
+    writer: &'a mut W,
+    path: Vec<String>, // path to the current JSON node
+    module_path: ModulePath,
+}
+
+impl<'a, W> Walker for JsonEncoder<'a, W> {
+    fn module_path(&mut self) -> &mut ModulePath {
+        &mut self.module_path
+    }
+}
+
+impl<'a, W: Write> JsonEncoder<'a, W> {
+    fn encode(&mut self, json: &JsonValue, rule: &BinProtRule) -> Result<()> {
+        match rule {
+            BinProtRule::Unit => match json {
+                JsonValue::Null => Ok(self.writer.bin_write_unit()?),
+                j => Err(self.invalid_type("null", j)),
+            },
+            BinProtRule::Bool => match json {
+                JsonValue::Bool(b) => Ok(self.writer.bin_write_bool(*b)?),
+                j => Err(self.invalid_type("a bool", j)),
+            },
+            BinProtRule::Int | BinProtRule::Int64 | BinProtRule::NativeInt => {
+                let i = self.read_i64(json)?;
+                self.writer.bin_write_integer(i)?;
+                Ok(())
+            }
+            BinProtRule::Int32 => {
+                let i = self.read_i64(json)?;
+                let i = i32::try_from(i).map_err(|_| self.invalid_type("an Int32", json))?;
+                self.writer.bin_write_integer(i)?;
+                Ok(())
+            }
+            BinProtRule::Nat0 => match json.as_u64() {
+                Some(n) => {
+                    self.writer.bin_write_nat0(n)?;
+                    Ok(())
+                }
+                None => Err(self.invalid_type("a non-negative integer", json)),
+            },
+            BinProtRule::Float => match json.as_f64() {
+                Some(f) => {
+                    self.writer.bin_write_float64(&f)?;
+                    Ok(())
+                }
+                None => Err(self.invalid_type("a float", json)),
+            },
+            BinProtRule::Char => {
+                let mut chars = json.as_str().map(|s| s.chars());
+                match chars.as_mut().map(|c| (c.next(), c.next())) {
+                    Some((Some(c), None)) if (c as u32) < 0x100 => {
+                        self.writer.bin_write_char(c)?;
+                        Ok(())
+                    }
+                    _ => Err(self.invalid_type("a single character string", json)),
+                }
+            }
+            BinProtRule::String => {
+                let bytes = self.read_bytes(json)?;
+                self.writer.bin_write_nat0(bytes.len() as u64)?;
+                self.writer.write_all(&bytes)?;
+                Ok(())
+            }
+            BinProtRule::Option(r) => match json {
+                JsonValue::Null => Ok(self.writer.bin_write_bool(false)?),
+                j => {
+                    self.writer.bin_write_bool(true)?;
+                    self.encode(j, r)
+                }
+            },
+            BinProtRule::Record(fields) => {
+                let obj = json
+                    .as_object()
+                    .ok_or_else(|| self.invalid_type("an object", json))?;
+                if let Some(name) = obj
+                    .keys()
+                    .find(|k| !fields.iter().any(|f| &*f.field_name == k.as_str()))
+                {
+                    return Err(Error::UnknownField {
+                        field: name.clone(),
+                        path: self.path(),
+                    });
+                }
+                for field in fields.iter() {
+                    let value = obj
+                        .get(&*field.field_name)
+                        .ok_or_else(|| Error::MissingField {
+                            field: field.field_name.to_string(),
+                            path: self.path(),
+                        })?;
+                    self.encode_child(field.field_name.to_string(), value, &field.field_rule)?;
+                }
+                Ok(())
+            }
+            BinProtRule::Tuple(rules) => {
+                let items = self.read_array(json, Some(rules.len()))?;
+                self.encode_items(items, rules.iter())
+            }
+            BinProtRule::List(r) => {
+                let items = self.read_array(json, None)?;
+                self.writer.bin_write_nat0(items.len() as u64)?;
+                self.encode_items(items, std::iter::repeat(&**r))
+            }
+            BinProtRule::Sum(summands) => self.encode_sum(json, summands),
+            BinProtRule::Reference(RuleRef::Resolved(payload)) => {
+                self.within_reference(payload, |e, r| e.encode(json, r))
+            }
+            BinProtRule::Custom(rules) => {
+                let path = self.module_path.as_str().to_string();
+                self.encode_custom(json, &path, rules)
+            }
+            BinProtRule::CustomForPath(path, rules) => self.encode_custom(json, path, rules),
+            r => Err(Error::Custom {
+                message: format!("No strategy to encode {:?}", r),
+            }),
+        }
+    }
+
+    // Constructors can be given in the yojson style ["Ctor", args...]
+    // or the plain style "Ctor" / {"Ctor": payload}
+    fn encode_sum(&mut self, json: &JsonValue, summands: &[Summand]) -> Result<()> {
+        let (name, args): (&str, Vec<&JsonValue>) = match json {
+            JsonValue::Array(list) => match list.split_first() {
+                Some((JsonValue::String(name), args)) => (name, args.iter().collect()),
+                _ => return Err(self.invalid_type("a constructor", json)),
+            },
+            JsonValue::String(name) => (name, vec![]),
+            JsonValue::Object(obj) if obj.len() == 1 => {
+                let (name, payload) = obj.iter().next().unwrap();
+                let (_, summand) = self.find_summand(summands, name)?;
+                let args = match (summand.ctor_args.len(), payload) {
+                    (1, p) => vec![p],
+                    (_, JsonValue::Array(items)) => items.iter().collect(),
+                    (_, p) => vec![p],
+                };
+                (name, args)
+            }
+            j => return Err(self.invalid_type("a constructor", j)),
+        };
+
+        let (index, summand) = self.find_summand(summands, name)?;
+        if args.len() != summand.ctor_args.len() {
+            return Err(self.invalid_type(
+                &format!("{} arguments to {}", summand.ctor_args.len(), name),
+                json,
+            ));
+        }
+        self.writer.bin_write_variant_index(index.into())?;
+        self.path.push(name.to_string());
+        let result = self.encode_items(args, summand.ctor_args.iter());
+        self.path.pop();
+        result
+    }
+
+    fn encode_custom(&mut self, json: &JsonValue, path: &str, rules: &[BinProtRule]) -> Result<()> {
+        let (len, element_rule) = custom_vector(path, rules)?;
+        let items = self.read_array(json, Some(len))?;
+        self.encode_items(items, std::iter::repeat(element_rule))?;
+        // zero byte terminator
+        self.writer.bin_write_unit()?;
+        Ok(())
+    }
+
+    fn encode_items<'j, 'r>(
+        &mut self,
+        items: impl IntoIterator<Item = &'j JsonValue>,
+        rules: impl Iterator<Item = &'r BinProtRule>,
+    ) -> Result<()> {
+        for (i, (item, rule)) in items.into_iter().zip(rules).enumerate() {
+            self.encode_child(i.to_string(), item, rule)?;
+        }
+        Ok(())
+    }
+
+    fn encode_child(
+        &mut self,
+        segment: String,
+        json: &JsonValue,
+        rule: &BinProtRule,
+    ) -> Result<()> {
+        self.path.push(segment);
+        let result = self.encode(json, rule);
+        self.path.pop();
+        result
+    }
+
+    fn find_summand<'s>(&self, summands: &'s [Summand], name: &str) -> Result<(u8, &'s Summand)> {
+        summand_index(summands, name.as_bytes()).ok_or_else(|| Error::UnknownConstructor {
+            name: name.to_string(),
+            path: self.path(),
+        })
+    }
+
+    // Integers may be given as JSON numbers or as strings (for 64 bit values)
+    fn read_i64(&self, json: &JsonValue) -> Result<i64> {
+        match json {
+            JsonValue::Number(n) => n.as_i64(),
+            JsonValue::String(s) => s.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| self.invalid_type("an integer", json))
+    }
+
+    // strings that are not valid utf-8 are exported as an array of their bytes (see value/json.rs)
+    fn read_bytes<'j>(&self, json: &'j JsonValue) -> Result<Cow<'j, [u8]>> {
+        match json {
+            JsonValue::String(s) => Some(Cow::Borrowed(s.as_bytes())),
+            JsonValue::Array(items) => items
+                .iter()
+                .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .map(Cow::Owned),
+            _ => None,
+        }
+        .ok_or_else(|| self.invalid_type("a string or an array of bytes", json))
+    }
+
+    fn read_array<'j>(
+        &self,
+        json: &'j JsonValue,
+        len: Option<usize>,
+    ) -> Result<&'j Vec<JsonValue>> {
+        match (json, len) {
+            (JsonValue::Array(items), Some(len)) if items.len() == len => Ok(items),
+            (JsonValue::Array(items), None) => Ok(items),
+            (j, Some(len)) => Err(self.invalid_type(&format!("an array of length {}", len), j)),
+            (j, None) => Err(self.invalid_type("an array", j)),
+        }
+    }
+
+    fn invalid_type(&self, expected: &str, found: &JsonValue) -> Error {
+        Error::InvalidJsonType {
+            expected: expected.to_string(),
+            found: found.to_string(),
+            path: self.path(),
+        }
+    }
+
+    // path to the current node in JSON Pointer syntax (see Value::pointer)
+    fn path(&self) -> String {
+        self.path.iter().map(|s| format!("/{}", s)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::{JsonStyle, Value};
+    use crate::Deserializer;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    const RULE: &str = r#"
+    [
+      "Record",
+      [
+        { "field_name": "first", "field_rule": ["Int32"] },
+        { "field_name": "second", "field_rule": ["Option", ["Sum", [
+          { "ctor_name": "One", "index": 0, "ctor_args": [] },
+          { "ctor_name": "Two", "index": 1, "ctor_args": [["Bool"], ["String"]] }
+        ]]] }
+      ]
+    ]
+    "#;
+
+    fn encode(json: JsonValue) -> Result<Vec<u8>> {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let mut output = Vec::new();
+        json_to_bin_prot(&json, &rule, &mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn encodes_yojson_and_plain_styles() {
+        let expected = vec![0x05, 0x01, 0x01, 0x01, 0x01, b'a'];
+        let yojson = json!({ "first": 5, "second": ["Two", true, "a"] });
+        let plain = json!({ "first": 5, "second": { "Two": [true, "a"] } });
+        assert_eq!(encode(yojson).unwrap(), expected);
+        assert_eq!(encode(plain).unwrap(), expected);
+
+        let constant = json!({ "first": "-1", "second": "One" });
+        assert_eq!(encode(constant).unwrap(), vec![0xff, 0xff, 0x01, 0x00]);
+        let yojson_constant = json!({ "first": 0, "second": ["One"] });
+        assert_eq!(encode(yojson_constant).unwrap(), vec![0x00, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn reports_missing_field() {
+        match encode(json!({ "first": 5 })) {
+            Err(Error::MissingField { field, path }) => {
+                assert_eq!(field, "second");
+                assert_eq!(path, "");
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn reports_unknown_field() {
+        match encode(json!({ "first": 5, "second": ["One"], "third": 1 })) {
+            Err(Error::UnknownField { field, path }) => {
+                assert_eq!(field, "third");
+                assert_eq!(path, "");
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn reports_wrong_type() {
+        match encode(json!({ "first": 5, "second": ["Two", 1, "a"] })) {
+            Err(Error::InvalidJsonType { path, .. }) => assert_eq!(path, "/second/Two/0"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(matches!(
+            encode(json!({ "first": 1_i64 << 40, "second": null })),
+            Err(Error::InvalidJsonType { .. })
+        ));
+    }
+
+    #[test]
+    fn binary_strings_round_trip() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let bytes = vec![0x05, 0x01, 0x01, 0x01, 0x02, 0xff, 0x01];
+        let mut de = Deserializer::from_reader_with_layout(bytes.as_slice(), rule);
+        let value = Value::deserialize(&mut de).unwrap();
+        assert_eq!(
+            value.pointer("/second/1"),
+            Some(&Value::String(vec![0xff, 0x01]))
+        );
+        for style in [JsonStyle::Yojson, JsonStyle::Plain] {
+            assert_eq!(encode(value.to_json_with(style)).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn reports_unknown_constructor() {
+        match encode(json!({ "first": 5, "second": ["Three"] })) {
+            Err(Error::UnknownConstructor { name, path }) => {
+                assert_eq!(name, "Three");
+                assert_eq!(path, "/second");
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
@@ -1,16 +1,21 @@
 mod array;
+#[cfg(feature = "rayon")]
+pub mod batch;
 mod consts;
 mod de;
 pub mod error;
 pub mod integers;
+mod json_encoder;
 mod loose_deserializer;
 mod read_ext;
 mod ser;
+pub mod sexp;
 pub mod value;
 mod write_ext;
 
 pub use array::OcamlArray;
 pub use de::{from_reader, Deserializer};
+pub use json_encoder::json_to_bin_prot;
 pub use read_ext::ReadBinProtExt;
 pub use ser::{to_writer, Serializer};
 pub use write_ext::WriteBinProtExt;
@@ -1,205 +1,437 @@
-use std::convert::TryInto;
 use std::io::Read;
+use std::sync::Arc;
 
-use crate::de::{Enum, MapAccess, SeqAccess};
 use crate::error::{Error, Result};
-use crate::value::layout::Summand;
-use crate::value::layout::{BinProtRule, BranchingIterator};
+use crate::value::layout::{
+    read_vector_terminator, select_summand, BinProtRule, ModulePath, RecordField, RuleRef, Walker,
+};
+use crate::value::{rule_name, CountingReader, VariantDeserializer};
 use crate::Deserializer as DS;
 use crate::ReadBinProtExt;
-use serde::de::{Deserializer, Visitor};
-use serde::{Deserialize, Serialize};
+use byteorder::{LittleEndian, ReadBytesExt};
+use serde::de::{
+    DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
 
-use byteorder::ReadBytesExt;
+impl<R: Read> DS<R> {
+    /// Follows the layout alongside the input, passing each node to the visitor as it is read.
+    /// Failures are reported as an `Error::LayoutDecode` giving where in the message they
+    /// occurred. Use `value::decode_value` to also get what was decoded before a failure
+    pub fn deserialize_loose<'de, V>(&mut self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let rule = self.layout.as_ref().ok_or(Error::WontImplement)?;
+        let mut state = State {
+            rdr: CountingReader::new(&mut self.rdr),
+            path: String::new(),
+            module_path: self.module_path.clone(),
+            current_type: None,
+        };
+        state.deserialize(rule, visitor, false)
+    }
+}
+
+// Where the layout deserializer is in the input and the layout
+struct State<R> {
+    rdr: CountingReader<R>,
+    path: String,
+    module_path: ModulePath,
+    current_type: Option<String>, // type declaration of the innermost reference
+}
+
+impl<R> Walker for State<R> {
+    fn module_path(&mut self) -> &mut ModulePath {
+        &mut self.module_path
+    }
+}
+
+impl<R: Read> State<R> {
+    // Lists are passed to the visitor as a newtype around their elements so that a Value can
+    // tell them apart from tuples (see value/visitor.rs), unless `seq` asks for a plain sequence
+    fn deserialize<'de, V>(&mut self, rule: &BinProtRule, visitor: V, seq: bool) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        if let BinProtRule::Reference(RuleRef::Resolved(payload)) = rule {
+            let outer_type = self.current_type.replace(payload.source_type_decl.clone());
+            let result = self.within_reference(payload, |s, r| s.deserialize(r, visitor, seq));
+            self.current_type = outer_type;
+            return result;
+        }
+        let start = self.rdr.count;
+        self.visit(rule, visitor, seq)
+            .map_err(|e| self.fail(e, rule, start))
+    }
 
-impl<'de, 'a, R: Read> DS<R> {
-    pub fn deserialize_loose<V>(&mut self, visitor: V) -> Result<V::Value>
+    fn visit<'de, V>(&mut self, rule: &BinProtRule, visitor: V, seq: bool) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        if let Some(iter) = &mut self.layout_iter {
-            loop {
-                match iter.next() {
-                    Ok(Some(rule)) => {
-                        match rule {
-                            BinProtRule::Unit => {
-                                return self.deserialize_unit(visitor);
-                            }
-                            BinProtRule::Record(fields) => {
-                                // Grab the field names from the rule to pass to the map access
-                                return visitor.visit_map(MapAccess::new(
-                                    self,
-                                    fields.into_iter().map(|f| f.field_name).rev().collect(),
-                                ));
-                            }
-                            BinProtRule::Tuple(items) => {
-                                return visitor.visit_seq(SeqAccess::new(self, items.len()));
-                            }
-                            BinProtRule::Sum(summands) => {
-                                // read the enum variant index.
-                                // We need this to select which variant layout to use
-                                // when deserializing the variants data
-                                let index = self.rdr.bin_read_variant_index()?;
-                                iter.branch(index.into()).expect("invalid branch index");
-                                return visitor.visit_enum(ValueEnum::new(
-                                    self,
-                                    summands[index as usize].clone(),
-                                ));
-                            }
-                            BinProtRule::Bool => {
-                                return self.deserialize_bool(visitor);
-                            }
-                            BinProtRule::Option(_) => {
-                                return self.deserialize_option(visitor);
-                            }
-                            BinProtRule::Reference(_) => {} // continue iterator
-                            BinProtRule::String => {
-                                return visitor.visit_bytes(&self.rdr.bin_read_bytes()?);
-                            }
-                            BinProtRule::Float => return self.deserialize_f64(visitor),
-                            BinProtRule::Char => {
-                                let c = self.rdr.read_u8()?;
-                                return visitor.visit_char(c as char);
-                            }
-                            BinProtRule::List(_) => {
-                                // read the length
-                                let len = self.rdr.bin_read_nat0()?;
-                                // request the iterator repeats the list elements the current number of times
-                                iter.repeat(len);
-                                // read the elements
-                                return visitor.visit_seq(SeqAccess::new(self, len));
-                            }
-                            BinProtRule::Int
-                            | BinProtRule::Int32
-                            | BinProtRule::Int64
-                            | BinProtRule::NativeInt => {
-                                return visitor.visit_i64(self.rdr.bin_read_integer()?);
-                            }
-                            BinProtRule::Polyvar(_)
-                            | BinProtRule::Vec(_, _)
-                            | BinProtRule::Nat0
-                            | BinProtRule::Hashtable(_)
-                            | BinProtRule::TypeVar(_)
-                            | BinProtRule::Bigstring
-                            | BinProtRule::SelfReference(_)
-                            | BinProtRule::TypeClosure(_, _)
-                            | BinProtRule::TypeAbstraction(_, _) => {
-                                return Err(Error::Custom {
-                                    message: format!("No strategy to deserialize {:?}", rule),
-                                })
-                            } // Don't know how to implement these yet
-                            BinProtRule::Custom(_) => {
-                                // the traverse function should never produce this
-                                return Err(Error::Custom {
-                                    message: "Cannot deserialize custom without providing context"
-                                        .to_string(),
-                                });
-                            }
-                            BinProtRule::CustomForPath(path, rules) => {
-                                // here is where custom deser methods can be looked up by path
-                                match path.as_str() {
-	                                "Pickles_type.Vector.Vector2" // the missing 's' on 'types' here is intention due to a big in layout producing code
-	                                | "Pickles_types.Vector.Vector4"
-	                                | "Pickles_types.Vector.Vector8"
-	                                | "Pickles_types.Vector.Vector17"
-	                                | "Pickles_types.Vector.Vector18" => {
-	                                    let element_rule = rules.first().unwrap();
-
-	                                    let len = match path.as_str() {
-	                                        "Pickles_type.Vector.Vector2" => 2,
-	                                        "Pickles_types.Vector.Vector4" => 4,
-	                                        "Pickles_types.Vector.Vector8" => 8,
-	                                        "Pickles_types.Vector.Vector17" => 17,
-	                                        "Pickles_types.Vector.Vector18" => 18,
-	                                        _ => {
-	                                            return Err(Error::Custom {
-	                                                message: "Unknown custom vector type"
-	                                                    .to_string(),
-	                                            })
-	                                        }
-	                                    };
-
-	                                    iter.push(element_rule.clone());
-	                                    iter.repeat(len);
-	                                    let result = visitor.visit_seq(SeqAccess::new(self, len));
-	                                    // burn the zero byte terminator
-	                                    assert!(self.rdr.read_u8()? == 0x00);
-
-	                                    return result;
-	                                }
-
-	                                _ => {
-	                                    // all the others are just BigInt probably so burn 32 bytes
-	                                    for _ in 0..(8 * 4) {
-	                                        // TODO: Read these and wrap as a proper Value variant
-	                                        self.rdr.read_u8()?;
-	                                    }
-	                                }
-	                            }
-                            }
-                        }
-                    }
-                    Err(_e) => {
-                        return Err(Error::Custom {
-                            message: "Iterator errored...".to_string(),
-                        })
-                    }
-                    Ok(None) => {
-                        return Err(Error::Custom {
-                            message: "Unxepected end of layout".to_string(),
-                        })
-                    }
+        match rule {
+            BinProtRule::Option(r) => match self.rdr.bin_read_bool()? {
+                false => visitor.visit_none(),
+                true => visitor.visit_some(Node {
+                    state: self,
+                    rule: r,
+                }),
+            },
+            BinProtRule::Record(fields) => {
+                let mut access = Fields {
+                    state: self,
+                    fields: fields.iter(),
+                    current: None,
+                };
+                let value = visitor.visit_map(&mut access)?;
+                match access.fields.len() {
+                    0 => Ok(value),
+                    n => Err(serde::de::Error::invalid_length(
+                        fields.len() - n,
+                        &"all fields",
+                    )),
                 }
             }
-        } else {
-            Err(Error::WontImplement)
+            BinProtRule::Tuple(rules) => self.visit_items(rules.iter(), visitor),
+            BinProtRule::List(r) => {
+                let len = self.rdr.bin_read_nat0::<u64>()? as usize;
+                self.visit_list(r, len, visitor, seq)
+            }
+            BinProtRule::Sum(summands) => {
+                let byte = self.rdr.bin_read_variant_index()?;
+                let summand = select_summand(summands, byte)?;
+                visitor.visit_enum(Sum {
+                    state: self,
+                    name: summand.ctor_name.clone(),
+                    index: byte,
+                    args: &summand.ctor_args,
+                })
+            }
+            BinProtRule::Custom(rules) => {
+                let (len, element) = self.module_path.custom_vector(rules)?;
+                let value = self.visit_list(element, len, visitor, seq)?;
+                let start = self.rdr.count;
+                read_vector_terminator(&mut self.rdr).map_err(|e| self.fail(e, rule, start))?;
+                Ok(value)
+            }
+            BinProtRule::Unit => {
+                self.rdr.bin_read_unit()?;
+                visitor.visit_unit()
+            }
+            BinProtRule::Bool => visitor.visit_bool(self.rdr.bin_read_bool()?),
+            BinProtRule::String => visitor.visit_byte_buf(self.rdr.bin_read_bytes()?),
+            BinProtRule::Char => visitor.visit_char(self.rdr.bin_read_char()?),
+            BinProtRule::Int | BinProtRule::Int32 | BinProtRule::Int64 | BinProtRule::NativeInt => {
+                visitor.visit_i64(self.rdr.bin_read_integer()?)
+            }
+            BinProtRule::Nat0 => visitor.visit_u32(self.rdr.bin_read_nat0()?),
+            BinProtRule::Float => visitor.visit_f64(self.rdr.read_f64::<LittleEndian>()?),
+            r => Err(Error::Custom {
+                message: format!("No strategy to decode {}", rule_name(r)),
+            }),
+        }
+    }
+
+    // visits the elements of a list or custom vector
+    fn visit_list<'de, V>(
+        &mut self,
+        element: &BinProtRule,
+        len: usize,
+        visitor: V,
+        seq: bool,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match seq {
+            true => self.visit_items(std::iter::repeat_n(element, len), visitor),
+            false => visitor.visit_newtype_struct(List {
+                state: self,
+                element,
+                len,
+            }),
         }
     }
+
+    // visits the children of a tuple or list, which are indexed by position
+    fn visit_items<'de, 'r, V>(
+        &mut self,
+        rules: impl ExactSizeIterator<Item = &'r BinProtRule>,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let len = rules.len();
+        let mut access = Items {
+            state: self,
+            rules: rules.enumerate(),
+        };
+        let value = visitor.visit_seq(&mut access)?;
+        match access.rules.len() {
+            0 => Ok(value),
+            n => Err(serde::de::Error::invalid_length(len - n, &"all elements")),
+        }
+    }
+
+    fn deserialize_child<'de, T>(
+        &mut self,
+        segment: &str,
+        rule: &BinProtRule,
+        seed: T,
+    ) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.within_child(segment, |state| seed.deserialize(Node { state, rule }))
+    }
+
+    fn within_child<T>(
+        &mut self,
+        segment: &str,
+        f: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let len = self.path.len();
+        self.path.push('/');
+        self.path.push_str(segment);
+        let result = f(self);
+        self.path.truncate(len);
+        result
+    }
+
+    // reports an error at the current node unless it was already reported by a child
+    fn fail(&self, error: Error, rule: &BinProtRule, offset: usize) -> Error {
+        if let Error::LayoutDecode { .. } = error {
+            return error;
+        }
+        let expected = match &self.current_type {
+            Some(t) => format!("{} ({})", rule_name(rule), t),
+            None => rule_name(rule).to_string(),
+        };
+        Error::LayoutDecode {
+            path: self.path.clone(),
+            offset,
+            expected,
+            error: Box::new(error),
+            partial: None,
+        }
+    }
+}
+
+// A node of the layout to be read from the input
+struct Node<'s, 'r, R> {
+    state: &'s mut State<R>,
+    rule: &'r BinProtRule,
 }
 
-// for accessing enums when using the loosely typed method
-// to deserialize into a Value
-pub struct ValueEnum<'a, R: Read> {
-    de: &'a mut DS<R>,
-    variant: Summand,
+impl<'de, R: Read> Deserializer<'de> for Node<'_, '_, R> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.state.deserialize(self.rule, visitor, false)
+    }
+
+    // a Rust sequence (e.g. a Vec) expects the elements of a list directly
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.state.deserialize(self.rule, visitor, true)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct EnumData {
-    pub index: u8,
-    pub name: String,
+// The elements of a list, passed to the visitor as a newtype
+struct List<'s, 'r, R> {
+    state: &'s mut State<R>,
+    element: &'r BinProtRule,
+    len: usize,
 }
 
-impl<'a, 'de, R: Read> ValueEnum<'a, R> {
-    fn new(de: &'a mut DS<R>, variant: Summand) -> Self {
-        Self { de, variant }
+impl<'de, R: Read> Deserializer<'de> for List<'_, '_, R> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let elements = std::iter::repeat_n(self.element, self.len);
+        self.state.visit_items(elements, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
     }
 }
 
-impl<'de, 'a, R: Read> serde::de::EnumAccess<'de> for ValueEnum<'a, R> {
+struct Items<'s, I, R> {
+    state: &'s mut State<R>,
+    rules: I,
+}
+
+impl<'de, 'r, I, R> SeqAccess<'de> for Items<'_, I, R>
+where
+    I: ExactSizeIterator<Item = (usize, &'r BinProtRule)>,
+    R: Read,
+{
     type Error = Error;
-    type Variant = Enum<'a, R>;
 
-    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
     where
-        V: serde::de::DeserializeSeed<'de>,
+        T: DeserializeSeed<'de>,
     {
-        let index = self.variant.index;
+        match self.rules.next() {
+            Some((i, rule)) => self
+                .state
+                .deserialize_child(&i.to_string(), rule, seed)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.rules.len())
+    }
+}
 
-        // bit of a hack here. visit_enum in the visitor is expecting to be able to
-        // deserialize the enum details (e.g. variant index and name) from the stream.
-        // Since in this case it comes from the layout file we need to serialize this data
-        // and then return the deserializer to be handled by visit_enum
+struct Fields<'s, 'r, R> {
+    state: &'s mut State<R>,
+    fields: std::slice::Iter<'r, RecordField>,
+    current: Option<&'r RecordField>,
+}
 
-        let enum_data = EnumData {
-            index: index.try_into().unwrap(),
-            name: self.variant.ctor_name,
-        };
-        let mut buf = Vec::<u8>::new();
-        crate::to_writer(&mut buf, &enum_data).unwrap();
-        let mut de = DS::from_reader(buf.as_slice());
-        let v = seed.deserialize(&mut de)?;
+impl<'de, R: Read> MapAccess<'de> for Fields<'_, '_, R> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        self.current = self.fields.next();
+        match self.current {
+            Some(field) => seed
+                .deserialize((&*field.field_name).into_deserializer())
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let field = self
+            .current
+            .take()
+            .ok_or_else(|| <Error as serde::de::Error>::custom("value requested before its key"))?;
+        self.state
+            .deserialize_child(&field.field_name, &field.field_rule, seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+// The arguments of a constructor are under its name, as a single node for one argument or as
+// a tuple for several
+struct Sum<'s, 'r, R> {
+    state: &'s mut State<R>,
+    name: Arc<str>,
+    index: u8,
+    args: &'r [BinProtRule],
+}
+
+impl<'de, R: Read> EnumAccess<'de> for Sum<'_, '_, R> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let value = seed.deserialize(VariantDeserializer {
+            name: self.name.clone(),
+            index: self.index,
+            arity: self.args.len(),
+        })?;
+        Ok((value, self))
+    }
+}
+
+impl<'de, R: Read> VariantAccess<'de> for Sum<'_, '_, R> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.args {
+            [] => Ok(()),
+            args => Err(serde::de::Error::invalid_length(
+                args.len(),
+                &"no arguments",
+            )),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.args {
+            [arg] => self.state.deserialize_child(&self.name, arg, seed),
+            args => self.state.within_child(&self.name, |state| {
+                seed.deserialize(Arguments { state, args })
+            }),
+        }
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let args = self.args;
+        self.state
+            .within_child(&self.name, |state| state.visit_items(args.iter(), visitor))
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let len = self.args.len();
+        self.tuple_variant(len, visitor)
+    }
+}
+
+// The arguments of a constructor with several, passed as a tuple
+struct Arguments<'s, 'r, R> {
+    state: &'s mut State<R>,
+    args: &'r [BinProtRule],
+}
+
+impl<'de, R: Read> Deserializer<'de> for Arguments<'_, '_, R> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.state.visit_items(self.args.iter(), visitor)
+    }
 
-        Ok((v, Enum::new(self.de, index.try_into().unwrap())))
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
     }
 }
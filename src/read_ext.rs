@@ -103,7 +103,7 @@ pub trait ReadBinProtExt: io::Read {
 
     fn bin_read_string(&mut self) -> Result<String> {
         let len = self.bin_read_nat0::<u64>()? as usize;
-        let mut buf = vec![0u8; len as usize];
+        let mut buf = vec![0u8; len];
         self.read_exact(&mut buf)?;
         let s = std::str::from_utf8(&buf)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
@@ -112,7 +112,7 @@ pub trait ReadBinProtExt: io::Read {
 
     fn bin_read_bytes(&mut self) -> Result<Vec<u8>> {
         let len = self.bin_read_nat0::<u64>()? as usize;
-        let mut buf = vec![0u8; len as usize];
+        let mut buf = vec![0u8; len];
         self.read_exact(&mut buf)?;
         Ok(buf)
     }
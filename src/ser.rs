@@ -35,7 +35,7 @@ where
     value.serialize(&mut Serializer::new(writer))
 }
 
-impl<'a, W> ser::Serializer for &'a mut Serializer<W>
+impl<W> ser::Serializer for &mut Serializer<W>
 where
     W: std::io::Write,
 {
@@ -299,7 +299,7 @@ where
 
 // This impl is SerializeSeq so these methods are called after `serialize_seq`
 // is called on the Serializer.
-impl<'a, W> ser::SerializeSeq for &'a mut Serializer<W>
+impl<W> ser::SerializeSeq for &mut Serializer<W>
 where
     W: std::io::Write,
 {
@@ -320,7 +320,7 @@ where
 }
 
 // Tuples are serialized just as the elements written consecutively
-impl<'a, W> ser::SerializeTuple for &'a mut Serializer<W>
+impl<W> ser::SerializeTuple for &mut Serializer<W>
 where
     W: std::io::Write,
 {
@@ -340,7 +340,7 @@ where
 }
 
 // Same thing but for tuple structs.
-impl<'a, W> ser::SerializeTupleStruct for &'a mut Serializer<W>
+impl<W> ser::SerializeTupleStruct for &mut Serializer<W>
 where
     W: std::io::Write,
 {
@@ -367,7 +367,7 @@ where
 // `serialize_entry` method allows serializers to optimize for the case where
 // key and value are both available simultaneously.
 // This is no more efficient in this case
-impl<'a, W> ser::SerializeMap for &'a mut Serializer<W>
+impl<W> ser::SerializeMap for &mut Serializer<W>
 where
     W: std::io::Write,
 {
@@ -396,7 +396,7 @@ where
 // Structs are written exactly the same as Tuples
 // Field values are written one after the other in order
 // keys are ignored
-impl<'a, W> ser::SerializeStruct for &'a mut Serializer<W>
+impl<W> ser::SerializeStruct for &mut Serializer<W>
 where
     W: std::io::Write,
 {
@@ -415,7 +415,7 @@ where
     }
 }
 
-impl<'a, W> ser::SerializeTupleVariant for &'a mut Serializer<W>
+impl<W> ser::SerializeTupleVariant for &mut Serializer<W>
 where
     W: std::io::Write,
 {
@@ -434,7 +434,7 @@ where
     }
 }
 
-impl<'a, W> ser::SerializeStructVariant for &'a mut Serializer<W>
+impl<W> ser::SerializeStructVariant for &mut Serializer<W>
 where
     W: std::io::Write,
 {
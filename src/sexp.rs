@@ -0,0 +1,377 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! S-expressions as used by Jane Street tooling.
+//!
+//! `Sexp` mirrors OCaml's `Sexp.t = Atom of string | List of t list`. Its Display implementation
+//! prints the machine readable text form (as produced by `Sexp.to_string_mach`) and it can be
+//! parsed back from text with `str::parse`.
+//!
+//! Sexp is also serialized in the same way as the bin_prot encoding of `Sexp.t`. Since `Error.t`
+//! and `Info.t` are encoded as their s-expression, an `Or_error.t` can be read as an `OrError<T>`.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+// The derived implementation writes the variant index followed by the payload
+// which matches the bin_prot encoding of the OCaml variant
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sexp {
+    /// An OCaml string, which need not be valid utf-8
+    Atom(#[serde(with = "atom")] Vec<u8>),
+    List(Vec<Sexp>),
+}
+
+/// OCaml's `Or_error.t`. The `Result` variants have the same encoding as OCaml's `Ok | Error`
+/// and an `Error.t` is encoded as its s-expression
+pub type OrError<T> = std::result::Result<T, Sexp>;
+
+/// Converts an `Or_error.t` into a Result of this crate, with the OCaml error as a readable message
+pub fn or_error_to_result<T>(r: OrError<T>) -> Result<T> {
+    r.map_err(|sexp| Error::OcamlError {
+        message: sexp.to_string_hum(),
+    })
+}
+
+impl Sexp {
+    /// Helper for building an atom from anything that can be displayed
+    pub fn atom<T: fmt::Display>(a: T) -> Self {
+        Sexp::Atom(a.to_string().into_bytes())
+    }
+
+    /// Human readable form as given by OCaml's `Error.to_string_hum`.
+    /// An atom is printed verbatim rather than quoted
+    pub fn to_string_hum(&self) -> String {
+        match self {
+            Sexp::Atom(s) => String::from_utf8_lossy(s).into_owned(),
+            list => list.to_string(),
+        }
+    }
+}
+
+// Atoms are bin_prot strings. They are written as a sequence of u8 if they are not valid utf-8,
+// which produces the same encoding (see value/ser.rs)
+mod atom {
+    use std::fmt;
+
+    use serde::de::{self, Visitor};
+    use serde::ser::SerializeSeq;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(bytes) {
+            Ok(s) => serializer.serialize_str(s),
+            Err(_) => {
+                let mut seq = serializer.serialize_seq(Some(bytes.len()))?;
+                for b in bytes {
+                    seq.serialize_element(b)?;
+                }
+                seq.end()
+            }
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_byte_buf(AtomVisitor)
+    }
+
+    struct AtomVisitor;
+
+    impl<'de> Visitor<'de> for AtomVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a string")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Vec<u8>, E> {
+            Ok(v.as_bytes().to_vec())
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+    }
+}
+
+// Atoms must be quoted if they would otherwise not parse back as a single atom.
+// Matches `Sexp.must_escape` from sexplib0
+fn must_quote(s: &[u8]) -> bool {
+    s.is_empty()
+        || s.windows(2).any(|w| w == b"#|" || w == b"|#")
+        || s.iter()
+            .any(|&b| b <= b' ' || b >= 0x7f || matches!(b, b'(' | b')' | b'"' | b';' | b'\\'))
+}
+
+// Bytes outside of printable ascii are written as a decimal escape,
+// as OCaml's `String.escaped` does
+fn write_quoted(f: &mut fmt::Formatter, s: &[u8]) -> fmt::Result {
+    f.write_str("\"")?;
+    for &b in s {
+        match b {
+            b'"' => f.write_str("\\\"")?,
+            b'\\' => f.write_str("\\\\")?,
+            b'\n' => f.write_str("\\n")?,
+            b'\t' => f.write_str("\\t")?,
+            b'\r' => f.write_str("\\r")?,
+            0x08 => f.write_str("\\b")?,
+            b' '..=b'~' => write!(f, "{}", b as char)?,
+            b => write!(f, "\\{:03}", b)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Sexp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Sexp::Atom(s) if must_quote(s) => write_quoted(f, s),
+            // an atom that doesn't need quoting is printable ascii
+            Sexp::Atom(s) => f.write_str(&String::from_utf8_lossy(s)),
+            Sexp::List(items) => {
+                f.write_str("(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+impl FromStr for Sexp {
+    type Err = Error;
+
+    /// Parse a single s-expression. Line comments starting with `;` are ignored
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = Parser {
+            input: s.as_bytes(),
+            pos: 0,
+        };
+        let sexp = parser.parse_sexp()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(sexp),
+            Some(_) => Err(parse_error("trailing input after s-expression", parser.pos)),
+        }
+    }
+}
+
+fn parse_error(message: &str, pos: usize) -> Error {
+    Error::SexpParse {
+        message: message.to_string(),
+        pos,
+    }
+}
+
+// Atoms are read as bytes since quoted atoms can contain escapes of any byte
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let b = self.peek()?;
+        self.pos += 1;
+        Some(b)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b) = self.peek() {
+            if b.is_ascii_whitespace() {
+                self.pos += 1;
+            } else if b == b';' {
+                while let Some(b) = self.next() {
+                    if b == b'\n' {
+                        break;
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn parse_sexp(&mut self) -> Result<Sexp> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(parse_error("unexpected end of input", self.pos)),
+            Some(b'(') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b')') => {
+                            self.pos += 1;
+                            return Ok(Sexp::List(items));
+                        }
+                        None => return Err(parse_error("unclosed list", self.pos)),
+                        Some(_) => items.push(self.parse_sexp()?),
+                    }
+                }
+            }
+            Some(b')') => Err(parse_error("unexpected closing paren", self.pos)),
+            Some(b'"') => {
+                self.pos += 1;
+                self.parse_quoted().map(Sexp::Atom)
+            }
+            Some(_) => {
+                let start = self.pos;
+                while let Some(b) = self.peek() {
+                    if b.is_ascii_whitespace() || matches!(b, b'(' | b')' | b'"' | b';') {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                Ok(Sexp::Atom(self.input[start..self.pos].to_vec()))
+            }
+        }
+    }
+
+    fn parse_quoted(&mut self) -> Result<Vec<u8>> {
+        let mut s = Vec::new();
+        loop {
+            let pos = self.pos;
+            match self.next() {
+                None => return Err(parse_error("unterminated string", pos)),
+                Some(b'"') => return Ok(s),
+                Some(b'\\') => match self.next() {
+                    Some(b'n') => s.push(b'\n'),
+                    Some(b't') => s.push(b'\t'),
+                    Some(b'r') => s.push(b'\r'),
+                    Some(b'b') => s.push(0x08),
+                    Some(b'\n') => {
+                        // escaped newline, skip leading whitespace on the next line
+                        while let Some(b' ') | Some(b'\t') = self.peek() {
+                            self.pos += 1;
+                        }
+                    }
+                    Some(d) if d.is_ascii_digit() => {
+                        let mut code = u32::from(d - b'0');
+                        for _ in 0..2 {
+                            match self.next() {
+                                Some(d) if d.is_ascii_digit() => {
+                                    code = code * 10 + u32::from(d - b'0')
+                                }
+                                _ => return Err(parse_error("invalid decimal escape", pos)),
+                            }
+                        }
+                        s.push(
+                            u8::try_from(code)
+                                .map_err(|_| parse_error("invalid decimal escape", pos))?,
+                        );
+                    }
+                    Some(b) => s.push(b),
+                    None => return Err(parse_error("unterminated string", self.pos)),
+                },
+                Some(b) => s.push(b),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prints_machine_format() {
+        let sexp = Sexp::List(vec![
+            Sexp::List(vec![Sexp::atom("field"), Sexp::atom(1)]),
+            Sexp::atom("two words"),
+            Sexp::atom(""),
+            Sexp::List(vec![]),
+        ]);
+        assert_eq!(sexp.to_string(), r#"((field 1) "two words" "" ())"#);
+    }
+
+    #[test]
+    fn parses_text() {
+        let sexp: Sexp = r#"  ((a "b c\n") ; comment
+             (d "e f"))  "#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            sexp,
+            Sexp::List(vec![
+                Sexp::List(vec![Sexp::atom("a"), Sexp::atom("b c\n")]),
+                Sexp::List(vec![Sexp::atom("d"), Sexp::atom("e f")]),
+            ])
+        );
+    }
+
+    #[test]
+    fn roundtrips_quoted_atoms() {
+        let sexp = Sexp::List(vec![
+            Sexp::atom("a\"b\\c\u{1}"),
+            Sexp::atom("(x)"),
+            Sexp::atom("\t"),
+            Sexp::Atom(vec![0xff, 0x01]),
+        ]);
+        assert_eq!(sexp.to_string().parse::<Sexp>().unwrap(), sexp);
+    }
+
+    #[test]
+    fn bin_prot_encoding() {
+        let sexp = Sexp::List(vec![Sexp::atom("a"), Sexp::List(vec![])]);
+        let mut output = Vec::new();
+        crate::to_writer(&mut output, &sexp).unwrap();
+        assert_eq!(output, vec![0x01, 0x02, 0x00, 0x01, b'a', 0x01, 0x00]);
+        let re_sexp: Sexp = crate::from_reader(output.as_slice()).unwrap();
+        assert_eq!(re_sexp, sexp);
+    }
+
+    #[test]
+    fn bin_prot_encoding_of_binary_atoms() {
+        let sexp = Sexp::Atom(vec![0xff, 0x01]);
+        let mut output = Vec::new();
+        crate::to_writer(&mut output, &sexp).unwrap();
+        assert_eq!(output, vec![0x00, 0x02, 0xff, 0x01]);
+        let re_sexp: Sexp = crate::from_reader(output.as_slice()).unwrap();
+        assert_eq!(re_sexp, sexp);
+    }
+
+    #[test]
+    fn or_error_encoding() {
+        let ok: OrError<i64> = crate::from_reader([0x00, 0x05].as_ref()).unwrap();
+        assert_eq!(or_error_to_result(ok).unwrap(), 5);
+
+        let bytes = [0x01, 0x00, 0x04, b'b', b'o', b'o', b'm'];
+        let err: OrError<i64> = crate::from_reader(bytes.as_ref()).unwrap();
+        assert_eq!(err, Err(Sexp::atom("boom")));
+        assert_eq!(
+            or_error_to_result(err).unwrap_err().to_string(),
+            "OCaml error: boom"
+        );
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        assert!(matches!(
+            "(a b".parse::<Sexp>(),
+            Err(Error::SexpParse { pos: 4, .. })
+        ));
+        assert!(matches!(
+            "a)".parse::<Sexp>(),
+            Err(Error::SexpParse { pos: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decoding Values along with the OCaml types they were declared as.
+//!
+//! Resolved references in a layout carry the type declaration and module path of the
+//! type being referenced. Normally these are skipped over when decoding. In annotated mode they
+//! are recorded against the path (see `walk`) of the node the reference describes.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use serde::Deserialize;
+
+use super::Value;
+use crate::error::Result;
+use crate::value::layout::{select_summand, BinProtRule, RuleRef};
+use crate::Deserializer;
+
+/// The OCaml type a node was decoded from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeInfo {
+    pub type_decl: String,
+    pub module_path: String,
+}
+
+/// A Value together with the OCaml types of its nodes
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnnotatedValue {
+    pub value: Value,
+    /// Types by node path. A node reached through a chain of references
+    /// (e.g. a type alias) has every type in the chain, outermost first
+    pub types: BTreeMap<String, Vec<TypeInfo>>,
+}
+
+impl AnnotatedValue {
+    /// Annotates an already decoded value using the layout it was decoded with
+    pub fn new(value: Value, rule: &BinProtRule) -> Self {
+        let mut annotator = Annotator {
+            path: String::new(),
+            types: BTreeMap::new(),
+        };
+        annotator.annotate(&value, rule, Vec::new());
+        Self {
+            value,
+            types: annotator.types,
+        }
+    }
+
+    /// The outermost type of the node at `path`, i.e. the type as it was written in the
+    /// declaration of its parent
+    pub fn type_of(&self, path: &str) -> Option<&TypeInfo> {
+        self.types.get(path).and_then(|types| types.first())
+    }
+}
+
+/// Decode a value using a layout, recording the OCaml type of each node
+pub fn from_reader_annotated<R: Read>(rdr: R, rule: &BinProtRule) -> Result<AnnotatedValue> {
+    let mut de = Deserializer::from_reader_with_layout(rdr, rule.clone());
+    let value: Value = Deserialize::deserialize(&mut de)?;
+    Ok(AnnotatedValue::new(value, rule))
+}
+
+struct Annotator {
+    path: String,
+    types: BTreeMap<String, Vec<TypeInfo>>,
+}
+
+impl Annotator {
+    // `pending` holds the types of references passed through since the last node
+    fn annotate(&mut self, value: &Value, rule: &BinProtRule, mut pending: Vec<TypeInfo>) {
+        if let BinProtRule::Reference(RuleRef::Resolved(payload)) = rule {
+            pending.push(TypeInfo {
+                type_decl: payload.source_type_decl.clone(),
+                module_path: payload.source_module_path.clone(),
+            });
+            return self.annotate(value, &payload.ref_rule, pending);
+        }
+        if !pending.is_empty() {
+            self.types
+                .entry(self.path.clone())
+                .or_default()
+                .extend(pending);
+        }
+
+        match (rule, value) {
+            (BinProtRule::Option(r), Value::Option(Some(v))) => self.annotate(v, r, Vec::new()),
+            (BinProtRule::Record(fields), Value::Record(values)) => {
+                for field in fields.iter() {
+                    if let Some((_, v)) = values.iter().find(|(k, _)| **k == *field.field_name) {
+                        self.annotate_child(&field.field_name, v, &field.field_rule);
+                    }
+                }
+            }
+            (BinProtRule::Tuple(rules), Value::Tuple(items)) => {
+                for (i, (item, r)) in items.iter().zip(rules.iter()).enumerate() {
+                    self.annotate_child(&i.to_string(), item, r);
+                }
+            }
+            (BinProtRule::List(r), Value::List(items))
+            | (BinProtRule::Vec(_, r), Value::List(items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    self.annotate_child(&i.to_string(), item, r);
+                }
+            }
+            (BinProtRule::Custom(rules), Value::List(items)) => {
+                if let Some(r) = rules.first() {
+                    for (i, item) in items.iter().enumerate() {
+                        self.annotate_child(&i.to_string(), item, r);
+                    }
+                }
+            }
+            (BinProtRule::Sum(summands), Value::Sum { name, index, value }) => {
+                let summand = match select_summand(summands, *index) {
+                    Ok(s) => s,
+                    Err(_) => return,
+                };
+                let len = self.path.len();
+                self.path.push('/');
+                self.path.push_str(name);
+                match (&summand.ctor_args[..], value.as_deref()) {
+                    ([r], Some(v)) => self.annotate(v, r, Vec::new()),
+                    (rules, Some(Value::Tuple(items))) => {
+                        for (i, (item, r)) in items.iter().zip(rules.iter()).enumerate() {
+                            self.annotate_child(&i.to_string(), item, r);
+                        }
+                    }
+                    _ => {}
+                }
+                self.path.truncate(len);
+            }
+            _ => {}
+        }
+    }
+
+    fn annotate_child(&mut self, segment: &str, value: &Value, rule: &BinProtRule) {
+        let len = self.path.len();
+        self.path.push('/');
+        self.path.push_str(segment);
+        self.annotate(value, rule, Vec::new());
+        self.path.truncate(len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULE: &str = r#"
+    [
+      "Record",
+      [
+        { "field_name": "fee", "field_rule": ["Reference", ["Resolved", {
+          "source_type_decl": "Currency.Fee.Stable.V1.t",
+          "source_module_path": "Currency.Fee.Stable.V1",
+          "ref_rule": ["Reference", ["Resolved", {
+            "source_type_decl": "Unsigned_extended.UInt64.t",
+            "source_module_path": "Unsigned_extended.UInt64",
+            "ref_rule": ["Int64"]
+          }]]
+        }]] },
+        { "field_name": "memo", "field_rule": ["Option", ["Reference", ["Resolved", {
+          "source_type_decl": "Signed_command_memo.t",
+          "ref_rule": ["String"]
+        }]]] }
+      ]
+    ]
+    "#;
+
+    #[test]
+    fn records_types_of_references() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let bytes = [0x05, 0x01, 0x02, b'h', b'i'];
+        let annotated = from_reader_annotated(bytes.as_ref(), &rule).unwrap();
+
+        assert_eq!(annotated.value["fee"], Value::Int(5));
+        assert_eq!(
+            annotated.types["/fee"],
+            vec![
+                TypeInfo {
+                    type_decl: "Currency.Fee.Stable.V1.t".to_string(),
+                    module_path: "Currency.Fee.Stable.V1".to_string(),
+                },
+                TypeInfo {
+                    type_decl: "Unsigned_extended.UInt64.t".to_string(),
+                    module_path: "Unsigned_extended.UInt64".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            annotated.type_of("/memo").unwrap().type_decl,
+            "Signed_command_memo.t"
+        );
+        assert_eq!(annotated.type_of(""), None);
+    }
+
+    #[test]
+    fn records_types_of_list_elements() {
+        let rule: BinProtRule = serde_json::from_str(
+            r#"["List", ["Reference", ["Resolved", {
+              "source_type_decl": "Flag.t",
+              "ref_rule": ["Bool"]
+            }]]]"#,
+        )
+        .unwrap();
+        let bytes = [0x02, 0x01, 0x00];
+        let annotated = from_reader_annotated(bytes.as_ref(), &rule).unwrap();
+
+        assert_eq!(annotated.type_of("/0").unwrap().type_decl, "Flag.t");
+        assert_eq!(annotated.type_of("/1").unwrap().type_decl, "Flag.t");
+    }
+
+    #[test]
+    fn records_types_of_constructor_arguments() {
+        let rule: BinProtRule = serde_json::from_str(
+            r#"["Sum", [
+              { "ctor_name": "A", "index": 3, "ctor_args": [] },
+              { "ctor_name": "B", "index": 7, "ctor_args": [["Reference", ["Resolved", {
+                "source_type_decl": "Flag.t",
+                "ref_rule": ["Bool"]
+              }]]] }
+            ]]"#,
+        )
+        .unwrap();
+        let bytes = [0x01, 0x01];
+        let annotated = from_reader_annotated(bytes.as_ref(), &rule).unwrap();
+
+        assert_eq!(annotated.type_of("/B").unwrap().type_decl, "Flag.t");
+    }
+}
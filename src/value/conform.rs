@@ -0,0 +1,402 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checking that a Value has the shape described by a layout rule.
+//!
+//! A Value that conforms to its rule can be serialized back to the bytes it would have been
+//! decoded from. Paths use the same syntax as `diff` and `walk`.
+
+use std::fmt;
+
+use super::Value;
+use crate::value::layout::{select_summand, BinProtRule, ModulePath, RuleRef, Summand, Walker};
+
+/// A single place where a value does not match its rule
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    pub path: String,
+    pub kind: MismatchKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MismatchKind {
+    /// The value is a different variant than the rule requires
+    WrongType { expected: String, found: String },
+    /// A field of the record rule is not in the value
+    MissingField(String),
+    /// The value has a field that is not in the record rule
+    UnexpectedField(String),
+    /// All fields are present but not in the order of the rule
+    FieldOrder,
+    /// The constructor index is not one of the rule's summands
+    IndexOutOfRange(u8),
+    /// The constructor name does not match the summand with its index
+    ConstructorName { expected: String, found: String },
+    /// A tuple, constructor arguments or fixed size vector of the wrong length
+    WrongLength { expected: usize, found: usize },
+    /// An integer or char that can't be represented by the rule e.g. an Int32 > i32::MAX
+    OutOfRange { dtype: String },
+    /// Values can't be checked against this rule
+    Unsupported(String),
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        write!(f, "{}: ", path)?;
+        match &self.kind {
+            MismatchKind::WrongType { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            MismatchKind::MissingField(name) => write!(f, "missing field {}", name),
+            MismatchKind::UnexpectedField(name) => write!(f, "unexpected field {}", name),
+            MismatchKind::FieldOrder => write!(f, "fields are not in layout order"),
+            MismatchKind::IndexOutOfRange(i) => write!(f, "constructor index {} out of range", i),
+            MismatchKind::ConstructorName { expected, found } => {
+                write!(f, "expected constructor {}, found {}", expected, found)
+            }
+            MismatchKind::WrongLength { expected, found } => {
+                write!(f, "expected length {}, found {}", expected, found)
+            }
+            MismatchKind::OutOfRange { dtype } => write!(f, "value out of range for {}", dtype),
+            MismatchKind::Unsupported(rule) => write!(f, "cannot check against {}", rule),
+        }
+    }
+}
+
+impl Value {
+    /// Checks this value against a layout rule, returning every mismatch found
+    pub fn conforms_to(&self, rule: &BinProtRule) -> Result<(), Vec<Mismatch>> {
+        let mut checker = Checker {
+            path: Vec::new(),
+            module_path: ModulePath::default(),
+            mismatches: Vec::new(),
+        };
+        checker.check(self, rule);
+        if checker.mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(checker.mismatches)
+        }
+    }
+
+    // name of the variant for error messages
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Unit => "Unit",
+            Value::Nat0(_) => "Nat0",
+            Value::Bool(_) => "Bool",
+            Value::String(_) => "String",
+            Value::Char(_) => "Char",
+            Value::Int(_) => "Int",
+            Value::Float(_) => "Float",
+            Value::Option(_) => "Option",
+            Value::Record(_) => "Record",
+            Value::Tuple(_) => "Tuple",
+            Value::Sum { .. } => "Sum",
+            Value::List(_) => "List",
+        }
+    }
+}
+
+struct Checker {
+    path: Vec<String>,
+    module_path: ModulePath,
+    mismatches: Vec<Mismatch>,
+}
+
+impl Walker for Checker {
+    fn module_path(&mut self) -> &mut ModulePath {
+        &mut self.module_path
+    }
+}
+
+impl Checker {
+    fn check(&mut self, value: &Value, rule: &BinProtRule) {
+        match (rule, value) {
+            (BinProtRule::Unit, Value::Unit)
+            | (BinProtRule::Nat0, Value::Nat0(_))
+            | (BinProtRule::Bool, Value::Bool(_))
+            | (BinProtRule::String, Value::String(_))
+            | (BinProtRule::Float, Value::Float(_))
+            | (BinProtRule::Int, Value::Int(_))
+            | (BinProtRule::Int64, Value::Int(_))
+            | (BinProtRule::NativeInt, Value::Int(_))
+            | (BinProtRule::Option(_), Value::Option(None)) => {}
+            (BinProtRule::Char, Value::Char(c)) => {
+                // chars are encoded as a single byte
+                if u32::from(*c) > 0xff {
+                    self.push(MismatchKind::OutOfRange {
+                        dtype: "Char".to_string(),
+                    });
+                }
+            }
+            (BinProtRule::Int32, Value::Int(i)) => {
+                if *i < i32::MIN.into() || *i > i32::MAX.into() {
+                    self.push(MismatchKind::OutOfRange {
+                        dtype: "Int32".to_string(),
+                    });
+                }
+            }
+            (BinProtRule::Option(r), Value::Option(Some(v))) => self.check(v, r),
+            (BinProtRule::Record(fields), Value::Record(values)) => {
+                let mut complete = values.len() == fields.len();
+                for field in fields.iter() {
+                    match values.iter().find(|(k, _)| **k == *field.field_name) {
+                        Some((_, v)) => self.check_child(&field.field_name, v, &field.field_rule),
+                        None => {
+                            complete = false;
+                            self.push(MismatchKind::MissingField(field.field_name.to_string()));
+                        }
+                    }
+                }
+                for (name, _) in values {
+                    if !fields.iter().any(|f| *f.field_name == **name) {
+                        self.push(MismatchKind::UnexpectedField(name.to_string()));
+                    }
+                }
+                // records are encoded in the order of the value's fields
+                if complete
+                    && fields
+                        .iter()
+                        .zip(values)
+                        .any(|(field, (name, _))| *field.field_name != **name)
+                {
+                    self.push(MismatchKind::FieldOrder);
+                }
+            }
+            (BinProtRule::Tuple(rules), Value::Tuple(items)) => self.check_items(items, rules),
+            (BinProtRule::List(r), Value::List(items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    self.check_child(&i.to_string(), item, r);
+                }
+            }
+            (BinProtRule::Vec(len, r), Value::List(items)) => self.check_fixed_list(items, *len, r),
+            (BinProtRule::Sum(summands), Value::Sum { name, index, value }) => {
+                self.check_sum(summands, name, *index, value.as_deref())
+            }
+            (BinProtRule::Reference(RuleRef::Resolved(payload)), v) => {
+                self.within_reference(payload, |c, r| c.check(v, r))
+            }
+            (BinProtRule::Custom(rules), Value::List(items)) => {
+                match self.module_path.custom_vector(rules) {
+                    Ok((len, r)) => self.check_fixed_list(items, len, r),
+                    Err(_) => self.push(MismatchKind::Unsupported(format!("{:?}", rule))),
+                }
+            }
+            (
+                BinProtRule::Polyvar(_)
+                | BinProtRule::Hashtable(_)
+                | BinProtRule::Bigstring
+                | BinProtRule::Reference(RuleRef::Unresolved(_))
+                | BinProtRule::TypeVar(_)
+                | BinProtRule::SelfReference(_)
+                | BinProtRule::TypeAbstraction(_, _)
+                | BinProtRule::TypeClosure(_, _)
+                | BinProtRule::CustomForPath(_, _),
+                _,
+            ) => self.push(MismatchKind::Unsupported(format!("{:?}", rule))),
+            (r, v) => self.push(MismatchKind::WrongType {
+                expected: expected_type(r),
+                found: v.type_name().to_string(),
+            }),
+        }
+    }
+
+    fn check_sum(&mut self, summands: &[Summand], name: &str, index: u8, value: Option<&Value>) {
+        let summand = match select_summand(summands, index) {
+            Ok(s) => s,
+            Err(_) => return self.push(MismatchKind::IndexOutOfRange(index)),
+        };
+        if *summand.ctor_name != *name {
+            self.push(MismatchKind::ConstructorName {
+                expected: summand.ctor_name.to_string(),
+                found: name.to_string(),
+            });
+        }
+        self.path.push(name.to_string());
+        match (&summand.ctor_args[..], value) {
+            ([], None) => {}
+            ([], Some(v)) => self.push(MismatchKind::WrongType {
+                expected: "no arguments".to_string(),
+                found: v.type_name().to_string(),
+            }),
+            (rules, None) => self.push(MismatchKind::WrongLength {
+                expected: rules.len(),
+                found: 0,
+            }),
+            ([r], Some(v)) => self.check(v, r),
+            (rules, Some(Value::Tuple(items))) => self.check_items(items, rules),
+            (_, Some(v)) => self.push(MismatchKind::WrongType {
+                expected: "Tuple".to_string(),
+                found: v.type_name().to_string(),
+            }),
+        }
+        self.path.pop();
+    }
+
+    fn check_items(&mut self, items: &[Value], rules: &[BinProtRule]) {
+        if items.len() != rules.len() {
+            return self.push(MismatchKind::WrongLength {
+                expected: rules.len(),
+                found: items.len(),
+            });
+        }
+        for (i, (item, rule)) in items.iter().zip(rules.iter()).enumerate() {
+            self.check_child(&i.to_string(), item, rule);
+        }
+    }
+
+    fn check_fixed_list(&mut self, items: &[Value], len: usize, rule: &BinProtRule) {
+        if items.len() != len {
+            return self.push(MismatchKind::WrongLength {
+                expected: len,
+                found: items.len(),
+            });
+        }
+        for (i, item) in items.iter().enumerate() {
+            self.check_child(&i.to_string(), item, rule);
+        }
+    }
+
+    fn check_child(&mut self, segment: &str, value: &Value, rule: &BinProtRule) {
+        self.path.push(segment.to_string());
+        self.check(value, rule);
+        self.path.pop();
+    }
+
+    fn push(&mut self, kind: MismatchKind) {
+        self.mismatches.push(Mismatch {
+            path: self.path.iter().map(|s| format!("/{}", s)).collect(),
+            kind,
+        });
+    }
+}
+
+// the Value variant expected for a rule
+fn expected_type(rule: &BinProtRule) -> String {
+    match rule {
+        BinProtRule::Unit => "Unit",
+        BinProtRule::Nat0 => "Nat0",
+        BinProtRule::Bool => "Bool",
+        BinProtRule::String => "String",
+        BinProtRule::Char => "Char",
+        BinProtRule::Float => "Float",
+        BinProtRule::Int | BinProtRule::Int32 | BinProtRule::Int64 | BinProtRule::NativeInt => {
+            "Int"
+        }
+        BinProtRule::Option(_) => "Option",
+        BinProtRule::Record(_) => "Record",
+        BinProtRule::Tuple(_) => "Tuple",
+        BinProtRule::Sum(_) => "Sum",
+        _ => "List",
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin_prot_value;
+
+    const RULE: &str = r#"
+    [
+      "Record",
+      [
+        { "field_name": "first", "field_rule": ["Int32"] },
+        { "field_name": "second", "field_rule": ["Sum", [
+          { "ctor_name": "One", "index": 0, "ctor_args": [] },
+          { "ctor_name": "Two", "index": 1, "ctor_args": [["Bool"], ["Vec", 2, ["Int"]]] }
+        ]] }
+      ]
+    ]
+    "#;
+
+    fn rule() -> BinProtRule {
+        serde_json::from_str(RULE).unwrap()
+    }
+
+    #[test]
+    fn conforming_value() {
+        let value = bin_prot_value!({ first: 1, second: Two#1(true, [1, 2]) });
+        assert_eq!(value.conforms_to(&rule()), Ok(()));
+    }
+
+    #[test]
+    fn reports_every_mismatch() {
+        let value = bin_prot_value!({ first: 2147483648_i64, second: Two#1(1, [1]) });
+        let mismatches = value.conforms_to(&rule()).unwrap_err();
+        let messages: Vec<String> = mismatches.iter().map(|m| m.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "/first: value out of range for Int32",
+                "/second/Two/0: expected Bool, found Int",
+                "/second/Two/1: expected length 2, found 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_record_and_constructor_mismatches() {
+        let value = bin_prot_value!({ second: Three#2, extra: () });
+        let kinds: Vec<MismatchKind> = value
+            .conforms_to(&rule())
+            .unwrap_err()
+            .into_iter()
+            .map(|m| m.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                MismatchKind::MissingField("first".to_string()),
+                MismatchKind::IndexOutOfRange(2),
+                MismatchKind::UnexpectedField("extra".to_string()),
+            ]
+        );
+
+        let value = bin_prot_value!({ second: One#0, first: 0 });
+        assert_eq!(
+            value.conforms_to(&rule()).unwrap_err()[0].kind,
+            MismatchKind::FieldOrder
+        );
+    }
+
+    #[test]
+    fn decoded_value_conforms() {
+        use crate::Deserializer;
+        use serde::Deserialize;
+
+        let rule: BinProtRule = serde_json::from_str(
+            r#"["Record", [{ "field_name": "items", "field_rule": ["List", ["Int"]] }]]"#,
+        )
+        .unwrap();
+        let bytes = [0x02, 0x05, 0x06];
+        let mut de = Deserializer::from_reader_with_layout(bytes.as_ref(), rule.clone());
+        let value = Value::deserialize(&mut de).unwrap();
+        assert_eq!(value.conforms_to(&rule), Ok(()));
+    }
+
+    #[test]
+    fn summands_are_selected_by_position() {
+        use crate::Deserializer;
+        use serde::Deserialize;
+
+        let rule: BinProtRule = serde_json::from_str(
+            r#"["Sum", [
+              { "ctor_name": "A", "index": 3, "ctor_args": [] },
+              { "ctor_name": "B", "index": 7, "ctor_args": [["Bool"]] }
+            ]]"#,
+        )
+        .unwrap();
+        let bytes = [0x01, 0x01];
+        let mut de = Deserializer::from_reader_with_layout(bytes.as_ref(), rule.clone());
+        let value = Value::deserialize(&mut de).unwrap();
+        assert_eq!(value, bin_prot_value!(B#1(true)));
+        assert_eq!(value.conforms_to(&rule), Ok(()));
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Conversions between a loosely typed Value and primitive Rust types
+
+use std::convert::TryFrom;
+
+use super::Value;
+use crate::error::Error;
+
+fn conversion_error(value: &Value, dtype: &str) -> Error {
+    Error::InvalidValueConversion {
+        value: format!("{:?}", value),
+        dtype: dtype.to_string(),
+    }
+}
+
+// Implements TryFrom<Value> for a primitive using a pattern to extract it
+macro_rules! impl_try_from {
+    ($typ:ty, $dtype:expr, $($pat:pat => $res:expr),+) => {
+        impl TryFrom<Value> for $typ {
+            type Error = Error;
+
+            fn try_from(value: Value) -> Result<Self, Self::Error> {
+                match value {
+                    $($pat => $res,)+
+                    v => Err(conversion_error(&v, $dtype)),
+                }
+            }
+        }
+    };
+}
+
+impl_try_from!(bool, "bool", Value::Bool(b) => Ok(b));
+impl_try_from!(char, "char", Value::Char(c) => Ok(c));
+impl_try_from!(f64, "f64", Value::Float(f) => Ok(f));
+impl_try_from!(Vec<u8>, "Vec<u8>", Value::String(bytes) => Ok(bytes));
+impl_try_from!(
+    i64, "i64",
+    Value::Int(i) => Ok(i),
+    Value::Nat0(n) => Ok(n.into())
+);
+impl_try_from!(
+    u32, "u32",
+    Value::Nat0(n) => Ok(n),
+    Value::Int(i) => u32::try_from(i).map_err(|_| conversion_error(&Value::Int(i), "u32"))
+);
+impl_try_from!(
+    String, "String",
+    Value::String(bytes) => String::from_utf8(bytes)
+        .map_err(|e| conversion_error(&Value::String(e.into_bytes()), "String"))
+);
+impl_try_from!((), "()", Value::Unit => Ok(()));
+
+// Implements From<primitive> for Value by wrapping it in a variant
+macro_rules! impl_from {
+    ($typ:ty, $v:ident => $res:expr) => {
+        impl From<$typ> for Value {
+            fn from($v: $typ) -> Self {
+                $res
+            }
+        }
+    };
+}
+
+impl_from!(bool, b => Value::Bool(b));
+impl_from!(char, c => Value::Char(c));
+impl_from!(f64, f => Value::Float(f));
+impl_from!(i64, i => Value::Int(i));
+impl_from!(i32, i => Value::Int(i.into()));
+impl_from!(Vec<u8>, bytes => Value::String(bytes));
+impl_from!(String, s => Value::String(s.into_bytes()));
+impl_from!(&str, s => Value::String(s.as_bytes().to_vec()));
+impl_from!((), _u => Value::Unit);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn converts_matching_variants() {
+        let i: i64 = Value::Int(-3).try_into().unwrap();
+        assert_eq!(i, -3);
+        let s: String = Value::String(b"hello".to_vec()).try_into().unwrap();
+        assert_eq!(s, "hello");
+        let n: u32 = Value::Int(7).try_into().unwrap();
+        assert_eq!(n, 7);
+    }
+
+    #[test]
+    fn errors_on_mismatched_variants() {
+        assert!(bool::try_from(Value::Int(1)).is_err());
+        assert!(u32::try_from(Value::Int(-1)).is_err());
+        assert!(String::try_from(Value::String(vec![0xff])).is_err());
+    }
+
+    #[test]
+    fn converts_primitives_into_values() {
+        assert_eq!(Value::from(3), Value::Int(3));
+        assert_eq!(Value::from("hi"), Value::String(b"hi".to_vec()));
+        assert_eq!(Value::from(()), Value::Unit);
+    }
+}
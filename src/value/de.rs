@@ -0,0 +1,660 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implements serde::Deserializer for Value so that a loosely typed value can be converted
+//! into a strongly typed Rust type without having to re-read the bin_prot bytes.
+//!
+//! Records are exposed as maps keyed by their layout field names and sums as enums
+//! keyed by their constructor name.
+
+use serde::de::{
+    self, value::BorrowedStrDeserializer, value::StrDeserializer, DeserializeOwned,
+    DeserializeSeed, IntoDeserializer, Unexpected, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use std::sync::Arc;
+
+use super::Value;
+use crate::error::{Error, Result};
+
+/// Interpret a `Value` as an instance of type `T`
+pub fn from_value<T>(value: Value) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(value)
+}
+
+impl Value {
+    fn unexpected(&self) -> Unexpected<'_> {
+        match self {
+            Value::Unit => Unexpected::Unit,
+            Value::Nat0(n) => Unexpected::Unsigned((*n).into()),
+            Value::Bool(b) => Unexpected::Bool(*b),
+            Value::String(b) => Unexpected::Bytes(b),
+            Value::Char(c) => Unexpected::Char(*c),
+            Value::Int(i) => Unexpected::Signed(*i),
+            Value::Float(f) => Unexpected::Float(*f),
+            Value::Option(_) => Unexpected::Option,
+            Value::Record(_) => Unexpected::Map,
+            Value::Tuple(_) | Value::List(_) => Unexpected::Seq,
+            Value::Sum { .. } => Unexpected::Enum,
+        }
+    }
+
+    fn invalid_type<E: de::Error>(&self, exp: &dyn de::Expected) -> E {
+        de::Error::invalid_type(self.unexpected(), exp)
+    }
+}
+
+// The number of constructor arguments a sum payload was decoded from (see visitor.rs)
+fn arity(payload: Option<&Value>) -> usize {
+    match payload {
+        None => 0,
+        Some(Value::Tuple(args)) => args.len(),
+        Some(_) => 1,
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Unit => visitor.visit_unit(),
+            Value::Nat0(n) => visitor.visit_u32(n),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::String(bytes) => match String::from_utf8(bytes) {
+                Ok(s) => visitor.visit_string(s),
+                Err(e) => visitor.visit_byte_buf(e.into_bytes()),
+            },
+            Value::Char(c) => visitor.visit_char(c),
+            Value::Int(i) => visitor.visit_i64(i),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::Option(None) => visitor.visit_none(),
+            Value::Option(Some(v)) => visitor.visit_some(*v),
+            Value::Record(fields) => visitor.visit_map(MapDeserializer {
+                iter: fields.into_iter(),
+                value: None,
+            }),
+            Value::Tuple(items) | Value::List(items) => visitor.visit_seq(SeqDeserializer {
+                iter: items.into_iter(),
+            }),
+            Value::Sum { name, index, value } => visitor.visit_enum(EnumDeserializer {
+                variant: VariantDeserializer {
+                    name,
+                    index,
+                    arity: arity(value.as_deref()),
+                },
+                value: VariantPayload(value.map(|v| *v)),
+            }),
+        }
+    }
+
+    // Anything that is not explicitly an option is treated as a present value
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Option(None) => visitor.visit_none(),
+            Value::Option(Some(v)) => visitor.visit_some(*v),
+            v => visitor.visit_some(v),
+        }
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::String(bytes) => visitor.visit_byte_buf(bytes),
+            v => Err(v.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Sum { .. } => self.deserialize_any(visitor),
+            v => Err(v.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        drop(self);
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &'de Value {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Unit => visitor.visit_unit(),
+            Value::Nat0(n) => visitor.visit_u32(*n),
+            Value::Bool(b) => visitor.visit_bool(*b),
+            Value::String(bytes) => match std::str::from_utf8(bytes) {
+                Ok(s) => visitor.visit_borrowed_str(s),
+                Err(_) => visitor.visit_borrowed_bytes(bytes),
+            },
+            Value::Char(c) => visitor.visit_char(*c),
+            Value::Int(i) => visitor.visit_i64(*i),
+            Value::Float(f) => visitor.visit_f64(*f),
+            Value::Option(None) => visitor.visit_none(),
+            Value::Option(Some(v)) => visitor.visit_some(&**v),
+            Value::Record(fields) => visitor.visit_map(MapRefDeserializer {
+                iter: fields.iter(),
+                value: None,
+            }),
+            Value::Tuple(items) | Value::List(items) => {
+                visitor.visit_seq(SeqRefDeserializer { iter: items.iter() })
+            }
+            Value::Sum { name, index, value } => visitor.visit_enum(EnumRefDeserializer {
+                variant: VariantDeserializer {
+                    name: name.clone(),
+                    index: *index,
+                    arity: arity(value.as_deref()),
+                },
+                value: VariantRefPayload(value.as_deref()),
+            }),
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Option(None) => visitor.visit_none(),
+            Value::Option(Some(v)) => visitor.visit_some(&**v),
+            v => visitor.visit_some(v),
+        }
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::String(bytes) => visitor.visit_borrowed_bytes(bytes),
+            v => Err(v.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Sum { .. } => self.deserialize_any(visitor),
+            v => Err(v.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+struct SeqDeserializer {
+    iter: std::vec::IntoIter<Value>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqDeserializer {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some(value) => seed.deserialize(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct SeqRefDeserializer<'de> {
+    iter: std::slice::Iter<'de, Value>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqRefDeserializer<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some(value) => seed.deserialize(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+// Record fields are exposed as a map from their layout field name to the value
+struct MapDeserializer {
+    iter: std::vec::IntoIter<(Arc<str>, Value)>,
+    value: Option<Value>,
+}
+
+impl<'de> de::MapAccess<'de> for MapDeserializer {
+    type Error = Error;
+
+    fn next_key_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                let key: StrDeserializer<Error> = (&*key).into_deserializer();
+                seed.deserialize(key).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::custom("value is missing")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapRefDeserializer<'de> {
+    iter: std::slice::Iter<'de, (Arc<str>, Value)>,
+    value: Option<&'de Value>,
+}
+
+impl<'de> de::MapAccess<'de> for MapRefDeserializer<'de> {
+    type Error = Error;
+
+    fn next_key_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(BorrowedStrDeserializer::new(key))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::custom("value is missing")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// Deserializes the variant identifier of a Sum.
+/// Rust enums identify the variant by its constructor name. When deserializing back into a
+/// Value the full EnumData (index, name and arity) is provided as a struct.
+pub(crate) struct VariantDeserializer {
+    pub(crate) name: Arc<str>,
+    pub(crate) index: u8,
+    pub(crate) arity: usize,
+}
+
+impl<'de> de::Deserializer<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_str(&self.name)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Value::Record(vec![
+            ("index".into(), Value::Int(self.index.into())),
+            ("name".into(), Value::String(self.name.as_bytes().to_vec())),
+            ("arity".into(), Value::Int(self.arity as i64)),
+        ])
+        .deserialize_any(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+struct EnumDeserializer {
+    variant: VariantDeserializer,
+    value: VariantPayload,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = VariantPayload;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, VariantPayload)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(self.variant)?;
+        Ok((variant, self.value))
+    }
+}
+
+// The payload of a Sum, None for a constant constructor
+struct VariantPayload(Option<Value>);
+
+impl<'de> de::VariantAccess<'de> for VariantPayload {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.0 {
+            None | Some(Value::Unit) => Ok(()),
+            Some(v) => Err(v.invalid_type(&"unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.0 {
+            Some(v) => seed.deserialize(v),
+            None => Err(constant_constructor(&"newtype variant")),
+        }
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Some(v @ (Value::Tuple(_) | Value::List(_))) => {
+                de::Deserializer::deserialize_any(v, visitor)
+            }
+            Some(v) => Err(v.invalid_type(&"tuple variant")),
+            None => Err(constant_constructor(&"tuple variant")),
+        }
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Some(v @ (Value::Record(_) | Value::Tuple(_))) => {
+                de::Deserializer::deserialize_any(v, visitor)
+            }
+            Some(v) => Err(v.invalid_type(&"struct variant")),
+            None => Err(constant_constructor(&"struct variant")),
+        }
+    }
+}
+
+fn constant_constructor(exp: &dyn de::Expected) -> Error {
+    de::Error::invalid_type(Unexpected::UnitVariant, exp)
+}
+
+struct EnumRefDeserializer<'de> {
+    variant: VariantDeserializer,
+    value: VariantRefPayload<'de>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumRefDeserializer<'de> {
+    type Error = Error;
+    type Variant = VariantRefPayload<'de>;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, VariantRefPayload<'de>)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(self.variant)?;
+        Ok((variant, self.value))
+    }
+}
+
+struct VariantRefPayload<'de>(Option<&'de Value>);
+
+impl<'de> de::VariantAccess<'de> for VariantRefPayload<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.0 {
+            None | Some(Value::Unit) => Ok(()),
+            Some(v) => Err(v.invalid_type(&"unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.0 {
+            Some(v) => seed.deserialize(v),
+            None => Err(constant_constructor(&"newtype variant")),
+        }
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Some(v @ (Value::Tuple(_) | Value::List(_))) => {
+                de::Deserializer::deserialize_any(v, visitor)
+            }
+            Some(v) => Err(v.invalid_type(&"tuple variant")),
+            None => Err(constant_constructor(&"tuple variant")),
+        }
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Some(v @ (Value::Record(_) | Value::Tuple(_))) => {
+                de::Deserializer::deserialize_any(v, visitor)
+            }
+            Some(v) => Err(v.invalid_type(&"struct variant")),
+            None => Err(constant_constructor(&"struct variant")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Outer {
+        first: i32,
+        second: Option<Inner>,
+        name: String,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum Inner {
+        One(bool),
+        Two(i64, bool),
+        Three,
+        Four { x: u8 },
+    }
+
+    fn sum(name: &str, index: u8, value: Option<Value>) -> Value {
+        Value::Sum {
+            name: name.into(),
+            index,
+            value: value.map(Box::new),
+        }
+    }
+
+    #[test]
+    fn record_to_struct() {
+        let val = Value::Record(vec![
+            ("first".into(), Value::Int(5)),
+            (
+                "second".into(),
+                Value::Option(Some(Box::new(sum("One", 0, Some(Value::Bool(true)))))),
+            ),
+            ("name".into(), Value::String(b"block".to_vec())),
+        ]);
+        let expected = Outer {
+            first: 5,
+            second: Some(Inner::One(true)),
+            name: "block".to_string(),
+        };
+        assert_eq!(Outer::deserialize(&val).unwrap(), expected);
+        assert_eq!(from_value::<Outer>(val).unwrap(), expected);
+    }
+
+    #[test]
+    fn sums_to_enum_variants() {
+        let two = sum(
+            "Two",
+            1,
+            Some(Value::Tuple(vec![Value::Int(-1), Value::Bool(false)])),
+        );
+        assert_eq!(from_value::<Inner>(two).unwrap(), Inner::Two(-1, false));
+
+        let three = sum("Three", 2, None);
+        assert_eq!(from_value::<Inner>(three).unwrap(), Inner::Three);
+
+        let four = sum(
+            "Four",
+            3,
+            Some(Value::Record(vec![("x".into(), Value::Int(3))])),
+        );
+        assert_eq!(from_value::<Inner>(four).unwrap(), Inner::Four { x: 3 });
+    }
+
+    #[test]
+    fn unknown_constructor_errors() {
+        assert!(from_value::<Inner>(sum("Five", 4, None)).is_err());
+    }
+
+    #[test]
+    fn value_roundtrips() {
+        let val = Value::Record(vec![
+            (
+                "a".into(),
+                sum(
+                    "Two",
+                    1,
+                    Some(Value::Tuple(vec![Value::Int(1), Value::Nat0(2)])),
+                ),
+            ),
+            ("b".into(), sum("Three", 2, None)),
+            ("c".into(), Value::Tuple(vec![Value::Char('x')])),
+        ]);
+        assert_eq!(from_value::<Value>(val.clone()).unwrap(), val);
+    }
+}
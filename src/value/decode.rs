@@ -0,0 +1,402 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decoding Values with a layout, keeping what was decoded when a message is malformed.
+//!
+//! Deserializing through `Deserializer::from_reader_with_layout` reports where decoding failed
+//! but the values it passed to the visitor are lost. Here the layout is followed directly so on
+//! failure the error also holds everything decoded up to that point.
+
+use std::io::Read;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use super::skip::CountingReader;
+use super::Value;
+use crate::error::{Error, Result};
+use crate::value::layout::{
+    capacity_for, read_vector_terminator, select_summand, BinProtRule, ModulePath, RuleRef, Walker,
+};
+use crate::ReadBinProtExt;
+
+/// Decode a value using a layout. On failure the error is an `Error::LayoutDecode` with the
+/// partial value set.
+///
+/// In the partial value of a failed decode, records, tuples and lists hold only their children
+/// before the failure and a constructor whose only argument failed with no partial value of
+/// its own has no payload.
+pub fn decode_value<R: Read>(rdr: R, rule: &BinProtRule) -> Result<Value> {
+    let mut decoder = Decoder {
+        rdr: CountingReader::new(rdr),
+        path: String::new(),
+        module_path: ModulePath::default(),
+        current_type: None,
+        failure: None,
+    };
+    decoder.decode(rule).map_err(|partial| {
+        let failure = decoder
+            .failure
+            .take()
+            .expect("failure is recorded on error");
+        Error::LayoutDecode {
+            path: failure.path,
+            offset: failure.offset,
+            expected: failure.expected,
+            error: Box::new(failure.error),
+            partial: partial.map(Box::new),
+        }
+    })
+}
+
+// what was decoded of a node that failed, if anything
+type Partial = Option<Value>;
+
+struct Failure {
+    path: String,
+    offset: usize,
+    expected: String,
+    error: Error,
+}
+
+struct Decoder<R> {
+    rdr: CountingReader<R>,
+    path: String,
+    module_path: ModulePath,
+    current_type: Option<String>, // type declaration of the innermost reference
+    failure: Option<Failure>,
+}
+
+impl<R> Walker for Decoder<R> {
+    fn module_path(&mut self) -> &mut ModulePath {
+        &mut self.module_path
+    }
+}
+
+impl<R: Read> Decoder<R> {
+    fn decode(&mut self, rule: &BinProtRule) -> std::result::Result<Value, Partial> {
+        match rule {
+            BinProtRule::Reference(RuleRef::Resolved(payload)) => {
+                let outer_type = self.current_type.replace(payload.source_type_decl.clone());
+                let result = self.within_reference(payload, |d, r| d.decode(r));
+                self.current_type = outer_type;
+                result
+            }
+            BinProtRule::Option(r) => match self.read(rule, |rdr| rdr.bin_read_bool())? {
+                false => Ok(Value::Option(None)),
+                true => self
+                    .decode(r)
+                    .map(|v| Value::Option(Some(Box::new(v))))
+                    .map_err(|p| p.map(|v| Value::Option(Some(Box::new(v))))),
+            },
+            BinProtRule::Record(fields) => {
+                let mut values = Vec::with_capacity(fields.len());
+                for field in fields.iter() {
+                    match self.decode_child(&field.field_name, &field.field_rule) {
+                        Ok(v) => values.push((field.field_name.clone(), v)),
+                        Err(p) => {
+                            values.extend(p.map(|v| (field.field_name.clone(), v)));
+                            return Err(Some(Value::Record(values)));
+                        }
+                    }
+                }
+                Ok(Value::Record(values))
+            }
+            BinProtRule::Tuple(rules) => self
+                .decode_items(rules.len(), |i| &rules[i])
+                .map(Value::Tuple)
+                .map_err(|items| Some(Value::Tuple(items))),
+            BinProtRule::List(r) => {
+                let len = self.read(rule, |rdr| rdr.bin_read_nat0::<u64>())? as usize;
+                self.decode_items(len, |_| r)
+                    .map(Value::List)
+                    .map_err(|items| Some(Value::List(items)))
+            }
+            BinProtRule::Sum(summands) => {
+                let (byte, summand) = self.read(rule, |rdr| {
+                    let byte = rdr.bin_read_variant_index()?;
+                    Ok((byte, select_summand(summands, byte)?))
+                })?;
+                let name = summand.ctor_name.clone();
+                let len = self.path.len();
+                self.path.push('/');
+                self.path.push_str(&name);
+                let result = match &summand.ctor_args[..] {
+                    [] => Ok(None),
+                    [arg] => self.decode(arg).map(Some),
+                    args => self
+                        .decode_items(args.len(), |i| &args[i])
+                        .map(|items| Some(Value::Tuple(items)))
+                        .map_err(|items| Some(Value::Tuple(items))),
+                };
+                self.path.truncate(len);
+                let sum = |value: Option<Value>| Value::Sum {
+                    name: name.clone(),
+                    index: byte,
+                    value: value.map(Box::new),
+                };
+                result.map(sum).map_err(|p| Some(sum(p)))
+            }
+            BinProtRule::Custom(rules) => {
+                let vector = self.module_path.custom_vector(rules);
+                let (len, element) = self.check(rule, vector)?;
+                let items = self
+                    .decode_items(len, |_| element)
+                    .map_err(|items| Some(Value::List(items)))?;
+                match self.read(rule, read_vector_terminator) {
+                    Ok(()) => Ok(Value::List(items)),
+                    Err(_) => Err(Some(Value::List(items))),
+                }
+            }
+            BinProtRule::Unit => self
+                .read(rule, |rdr| rdr.bin_read_unit())
+                .map(|_| Value::Unit),
+            BinProtRule::Bool => self.read(rule, |rdr| rdr.bin_read_bool()).map(Value::Bool),
+            BinProtRule::String => self
+                .read(rule, |rdr| rdr.bin_read_bytes())
+                .map(Value::String),
+            BinProtRule::Char => self.read(rule, |rdr| rdr.bin_read_char()).map(Value::Char),
+            BinProtRule::Int | BinProtRule::Int32 | BinProtRule::Int64 | BinProtRule::NativeInt => {
+                self.read(rule, |rdr| rdr.bin_read_integer())
+                    .map(Value::Int)
+            }
+            BinProtRule::Nat0 => self.read(rule, |rdr| rdr.bin_read_nat0()).map(Value::Nat0),
+            BinProtRule::Float => self
+                .read(rule, |rdr| Ok(rdr.read_f64::<LittleEndian>()?))
+                .map(Value::Float),
+            r => self.check(
+                r,
+                Err(Error::Custom {
+                    message: format!("No strategy to decode {}", rule_name(r)),
+                }),
+            ),
+        }
+    }
+
+    fn decode_child(
+        &mut self,
+        segment: &str,
+        rule: &BinProtRule,
+    ) -> std::result::Result<Value, Partial> {
+        let len = self.path.len();
+        self.path.push('/');
+        self.path.push_str(segment);
+        let result = self.decode(rule);
+        self.path.truncate(len);
+        result
+    }
+
+    // decodes `len` children indexed by position, on failure giving those decoded so far
+    fn decode_items<'r>(
+        &mut self,
+        len: usize,
+        rule: impl Fn(usize) -> &'r BinProtRule,
+    ) -> std::result::Result<Vec<Value>, Vec<Value>> {
+        let mut items = Vec::with_capacity(capacity_for(len));
+        for i in 0..len {
+            match self.decode_child(&i.to_string(), rule(i)) {
+                Ok(v) => items.push(v),
+                Err(p) => {
+                    items.extend(p);
+                    return Err(items);
+                }
+            }
+        }
+        Ok(items)
+    }
+
+    // reads from the input, recording a failure against `rule` at the start of the read on error
+    fn read<T>(
+        &mut self,
+        rule: &BinProtRule,
+        f: impl FnOnce(&mut CountingReader<R>) -> Result<T>,
+    ) -> std::result::Result<T, Partial> {
+        let start = self.rdr.count;
+        f(&mut self.rdr).map_err(|e| self.fail(e, rule, start))
+    }
+
+    // records a failure against `rule` at the current offset if `result` is an error
+    fn check<T>(
+        &mut self,
+        rule: &BinProtRule,
+        result: Result<T>,
+    ) -> std::result::Result<T, Partial> {
+        let offset = self.rdr.count;
+        result.map_err(|e| self.fail(e, rule, offset))
+    }
+
+    fn fail(&mut self, error: Error, rule: &BinProtRule, offset: usize) -> Partial {
+        if self.failure.is_none() {
+            let expected = match &self.current_type {
+                Some(t) => format!("{} ({})", rule_name(rule), t),
+                None => rule_name(rule).to_string(),
+            };
+            self.failure = Some(Failure {
+                path: self.path.clone(),
+                offset,
+                expected,
+                error,
+            });
+        }
+        None
+    }
+}
+
+pub(crate) fn rule_name(rule: &BinProtRule) -> &'static str {
+    match rule {
+        BinProtRule::Option(_) => "option",
+        BinProtRule::Record(_) => "record",
+        BinProtRule::Tuple(_) => "tuple",
+        BinProtRule::Sum(_) => "sum",
+        BinProtRule::Polyvar(_) => "polymorphic variant",
+        BinProtRule::List(_) => "list",
+        BinProtRule::Vec(_, _) => "vector",
+        BinProtRule::Hashtable(_) => "hashtable",
+        BinProtRule::Reference(_) => "reference",
+        BinProtRule::Custom(_) | BinProtRule::CustomForPath(_, _) => "custom",
+        BinProtRule::Unit => "unit",
+        BinProtRule::Bool => "bool",
+        BinProtRule::String => "string",
+        BinProtRule::Char => "char",
+        BinProtRule::Int | BinProtRule::Int32 | BinProtRule::Int64 | BinProtRule::NativeInt => {
+            "int"
+        }
+        BinProtRule::Nat0 => "nat0",
+        BinProtRule::Float => "float",
+        _ => "unsupported rule",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bin_prot_value, Deserializer};
+    use serde::Deserialize;
+
+    fn decode(bytes: &[u8], rule: &BinProtRule) -> Result<Value> {
+        decode_value(bytes, rule)
+    }
+
+    // deserializes through the layout Deserializer, which keeps no partial value
+    fn deserialize(bytes: &[u8], rule: &BinProtRule) -> Result<Value> {
+        let mut de = Deserializer::from_reader_with_layout(bytes, rule.clone());
+        Value::deserialize(&mut de)
+    }
+
+    const RULE: &str = r#"
+    ["Record", [
+      { "field_name": "first", "field_rule": ["Int"] },
+      { "field_name": "second", "field_rule": ["List", ["Sum", [
+        { "ctor_name": "One", "index": 0, "ctor_args": [] },
+        { "ctor_name": "Two", "index": 1, "ctor_args": [["Reference", ["Resolved", {
+          "source_type_decl": "Flag.t",
+          "ref_rule": ["Bool"]
+        }]]] }
+      ]]] },
+      { "field_name": "third", "field_rule": ["String"] }
+    ]]
+    "#;
+
+    #[test]
+    fn decodes_with_layout() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let bytes = [0x05, 0x02, 0x00, 0x01, 0x01, 0x01, b'a'];
+        let expected = bin_prot_value!({ first: 5, second: [One#0, Two#1(true)], third: "a" });
+        assert_eq!(decode(&bytes, &rule).unwrap(), expected);
+        assert_eq!(deserialize(&bytes, &rule).unwrap(), expected);
+    }
+
+    #[test]
+    fn reports_where_decoding_failed() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let bytes = [0x05, 0x02, 0x00, 0x01, 0x07];
+        match decode(&bytes, &rule) {
+            Err(Error::LayoutDecode {
+                path,
+                offset,
+                expected,
+                error,
+                partial,
+            }) => {
+                assert_eq!(path, "/second/1/Two");
+                assert_eq!(offset, 4);
+                assert_eq!(expected, "bool (Flag.t)");
+                assert!(matches!(*error, Error::InvalidByte { byte: 0x07, .. }));
+                assert_eq!(
+                    *partial.unwrap(),
+                    bin_prot_value!({ first: 5, second: [One#0, Two#1] })
+                );
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn reports_end_of_input() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let bytes = [0x05, 0x00, 0x03, b'a'];
+        match decode(&bytes, &rule) {
+            Err(Error::LayoutDecode {
+                path,
+                offset,
+                partial,
+                ..
+            }) => {
+                // the string length was read but not all of its bytes
+                assert_eq!(path, "/third");
+                assert_eq!(offset, 2);
+                assert_eq!(*partial.unwrap(), bin_prot_value!({ first: 5, second: [] }));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn deserializer_reports_where_decoding_failed() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let bytes = [0x05, 0x02, 0x00, 0x01, 0x07];
+        match deserialize(&bytes, &rule) {
+            Err(Error::LayoutDecode {
+                path,
+                offset,
+                expected,
+                error,
+                partial,
+            }) => {
+                assert_eq!(path, "/second/1/Two");
+                assert_eq!(offset, 4);
+                assert_eq!(expected, "bool (Flag.t)");
+                assert!(matches!(*error, Error::InvalidByte { byte: 0x07, .. }));
+                assert!(partial.is_none());
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // a multi byte integer is reported where it starts
+        let bytes = [0xfe, 0x01];
+        for result in [decode(&bytes, &rule), deserialize(&bytes, &rule)] {
+            match result {
+                Err(Error::LayoutDecode { path, offset, .. }) => {
+                    assert_eq!(path, "/first");
+                    assert_eq!(offset, 0);
+                }
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn vectors_are_lists() {
+        let rule: BinProtRule = serde_json::from_str(
+            r#"["Reference", ["Resolved", {
+              "source_type_decl": "Pickles_types.Vector.Vector2.t",
+              "source_module_path": "Pickles_type.Vector.Vector2",
+              "ref_rule": ["Custom", [["Bool"]]]
+            }]]"#,
+        )
+        .unwrap();
+        let bytes = [0x01, 0x00, 0x00];
+        let expected = Value::List(vec![Value::Bool(true), Value::Bool(false)]);
+        assert_eq!(decode(&bytes, &rule).unwrap(), expected);
+        assert_eq!(deserialize(&bytes, &rule).unwrap(), expected);
+    }
+}
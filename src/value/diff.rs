@@ -0,0 +1,251 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Structural comparison of two Values.
+//!
+//! Paths use the JSON Pointer syntax of `Value::pointer`, with a constructor name as the
+//! segment when descending into the payload of a Sum e.g. `/second/Two/0`.
+
+use std::fmt;
+
+use super::Value;
+
+/// The way in which two values differ at a given path
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DifferenceKind {
+    /// Both sides are present but have different values or different variants
+    Changed,
+    /// Only present on the right (a record field, list element or the contents of an option)
+    Added,
+    /// Only present on the left
+    Removed,
+    /// Both sides are the same sum type but use a different constructor
+    DifferentConstructor,
+}
+
+/// A single difference between two values
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference {
+    pub path: String,
+    pub kind: DifferenceKind,
+    pub left: Option<Value>,
+    pub right: Option<Value>,
+}
+
+/// Compares two values and returns every location at which they differ.
+/// An empty result means the values are equal
+pub fn diff(left: &Value, right: &Value) -> Vec<Difference> {
+    let mut differ = Differ {
+        path: Vec::new(),
+        differences: Vec::new(),
+    };
+    differ.compare(left, right);
+    differ.differences
+}
+
+/// Renders differences one after another in a human readable form.
+/// Each difference is a header line followed by `-` for the left and `+` for the right side
+pub fn render_diff(differences: &[Difference]) -> String {
+    differences.iter().map(|d| d.to_string()).collect()
+}
+
+impl fmt::Display for DifferenceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            DifferenceKind::Changed => "changed",
+            DifferenceKind::Added => "added",
+            DifferenceKind::Removed => "removed",
+            DifferenceKind::DifferentConstructor => "different constructor",
+        })
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        writeln!(f, "{}: {}", path, self.kind)?;
+        if let Some(left) = &self.left {
+            writeln!(f, "-{}", left.to_sexp())?;
+        }
+        if let Some(right) = &self.right {
+            writeln!(f, "+{}", right.to_sexp())?;
+        }
+        Ok(())
+    }
+}
+
+struct Differ {
+    path: Vec<String>,
+    differences: Vec<Difference>,
+}
+
+impl Differ {
+    fn compare(&mut self, left: &Value, right: &Value) {
+        match (left, right) {
+            (Value::Record(l), Value::Record(r)) => {
+                for (name, lv) in l {
+                    match r.iter().find(|(k, _)| k == name) {
+                        Some((_, rv)) => self.compare_child(name, lv, rv),
+                        None => self.push_child(name, DifferenceKind::Removed, Some(lv), None),
+                    }
+                }
+                for (name, rv) in r {
+                    if !l.iter().any(|(k, _)| k == name) {
+                        self.push_child(name, DifferenceKind::Added, None, Some(rv));
+                    }
+                }
+            }
+            (Value::List(l), Value::List(r)) | (Value::Tuple(l), Value::Tuple(r)) => {
+                for (i, (lv, rv)) in l.iter().zip(r).enumerate() {
+                    self.compare_child(&i.to_string(), lv, rv);
+                }
+                for (i, lv) in l.iter().enumerate().skip(r.len()) {
+                    self.push_child(&i.to_string(), DifferenceKind::Removed, Some(lv), None);
+                }
+                for (i, rv) in r.iter().enumerate().skip(l.len()) {
+                    self.push_child(&i.to_string(), DifferenceKind::Added, None, Some(rv));
+                }
+            }
+            (Value::Option(Some(l)), Value::Option(Some(r))) => self.compare(l, r),
+            (Value::Option(Some(l)), Value::Option(None)) => {
+                self.push(DifferenceKind::Removed, Some(l), None)
+            }
+            (Value::Option(None), Value::Option(Some(r))) => {
+                self.push(DifferenceKind::Added, None, Some(r))
+            }
+            (
+                Value::Sum {
+                    name: ln,
+                    value: lv,
+                    ..
+                },
+                Value::Sum {
+                    name: rn,
+                    value: rv,
+                    ..
+                },
+            ) => {
+                if ln == rn {
+                    match (lv, rv) {
+                        (Some(lv), Some(rv)) => self.compare_child(ln, lv, rv),
+                        (None, None) => {}
+                        _ => self.push(DifferenceKind::Changed, Some(left), Some(right)),
+                    }
+                } else {
+                    self.push(
+                        DifferenceKind::DifferentConstructor,
+                        Some(left),
+                        Some(right),
+                    );
+                }
+            }
+            (l, r) => {
+                if l != r {
+                    self.push(DifferenceKind::Changed, Some(l), Some(r));
+                }
+            }
+        }
+    }
+
+    fn compare_child(&mut self, segment: &str, left: &Value, right: &Value) {
+        self.path.push(segment.to_string());
+        self.compare(left, right);
+        self.path.pop();
+    }
+
+    fn push_child(
+        &mut self,
+        segment: &str,
+        kind: DifferenceKind,
+        left: Option<&Value>,
+        right: Option<&Value>,
+    ) {
+        self.path.push(segment.to_string());
+        self.push(kind, left, right);
+        self.path.pop();
+    }
+
+    fn push(&mut self, kind: DifferenceKind, left: Option<&Value>, right: Option<&Value>) {
+        self.differences.push(Difference {
+            path: self.path.iter().map(|s| format!("/{}", s)).collect(),
+            kind,
+            left: left.cloned(),
+            right: right.cloned(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(first: i64, second: Value, third: Vec<Value>) -> Value {
+        Value::Record(vec![
+            ("first".into(), Value::Int(first)),
+            ("second".into(), second),
+            ("third".into(), Value::List(third)),
+        ])
+    }
+
+    #[test]
+    fn equal_values_have_no_differences() {
+        let a = record(1, Value::sum("One", 0, vec![]), vec![Value::Bool(true)]);
+        assert_eq!(diff(&a, &a.clone()), vec![]);
+    }
+
+    #[test]
+    fn finds_differences_with_paths() {
+        let a = record(
+            1,
+            Value::sum("Two", 1, vec![Value::Bool(true), Value::Int(2)]),
+            vec![Value::Int(1), Value::Int(2)],
+        );
+        let b = record(
+            1,
+            Value::sum("Two", 1, vec![Value::Bool(false), Value::Int(2)]),
+            vec![Value::Int(1)],
+        );
+        let differences = diff(&a, &b);
+        assert_eq!(
+            differences,
+            vec![
+                Difference {
+                    path: "/second/Two/0".to_string(),
+                    kind: DifferenceKind::Changed,
+                    left: Some(Value::Bool(true)),
+                    right: Some(Value::Bool(false)),
+                },
+                Difference {
+                    path: "/third/1".to_string(),
+                    kind: DifferenceKind::Removed,
+                    left: Some(Value::Int(2)),
+                    right: None,
+                },
+            ]
+        );
+        assert_eq!(
+            render_diff(&differences),
+            "/second/Two/0: changed\n-true\n+false\n/third/1: removed\n-2\n"
+        );
+        for d in &differences {
+            assert_eq!(a.pointer(&d.path), d.left.as_ref());
+        }
+    }
+
+    #[test]
+    fn different_constructors_are_not_descended() {
+        let a = Value::sum("One", 0, vec![]);
+        let b = Value::sum("Two", 1, vec![Value::Bool(true)]);
+        let differences = diff(&a, &b);
+        assert_eq!(differences.len(), 1);
+        assert_eq!(differences[0].kind, DifferenceKind::DifferentConstructor);
+        assert_eq!(
+            differences[0].to_string(),
+            "/: different constructor\n-One\n+(Two true)\n"
+        );
+    }
+}
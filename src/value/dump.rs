@@ -0,0 +1,265 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Annotated hex dumps of encoded messages.
+//!
+//! Each line shows the bytes of one node, in the style of the Jane Street bin_prot expect tests
+//! (bytes reversed and padded on the left with `..`), followed by the path of the node and what
+//! the bytes decoded to. Leaf values are shown as S-expressions. Container nodes get a line for
+//! their header bytes, i.e. the length of a list, the tag of an option or the constructor of a sum.
+//! If decoding fails the rejected byte is marked with `^^` and the error is shown.
+//!
+//! ```text
+//! .. .. .. .. .. .. .. 01 -> /first Some
+//! .. .. .. .. .. .. .. 05 -> /first/0 5
+//! .. .. .. .. .. .. .. 02 -> /first/1 error: Invalid byte ...
+//!                      ^^
+//! ```
+
+use std::fmt::Write;
+
+use super::plan::Leaf;
+use super::span::{partial_spans, Failure, Span};
+use crate::error::Error;
+use crate::value::layout::{BinProtRule, RuleRef};
+use crate::ReadBinProtExt;
+
+/// The number of bytes shown on each line. Longer nodes continue on the following lines
+const BYTES_PER_LINE: usize = 8;
+
+/// Renders a hex dump of a message annotated using its layout
+pub fn hex_dump(bytes: &[u8], rule: &BinProtRule) -> String {
+    let (spans, failure) = partial_spans(bytes, rule);
+    let mut out = String::new();
+    // the trailers of the spans being dumped, written once all of their descendants are
+    let mut trailers: Vec<Trailer> = Vec::new();
+    for (i, span) in spans.iter().enumerate() {
+        while let Some(trailer) = trailers.last() {
+            if is_descendant(&span.path, trailer.path) {
+                break;
+            }
+            trailers.pop().unwrap().write(&mut out, bytes);
+        }
+
+        // descendants directly follow a span so its own bytes are those before the first
+        // descendant and after the last
+        let descendants = spans[i + 1..]
+            .iter()
+            .take_while(|s| is_descendant(&s.path, &span.path))
+            .collect::<Vec<_>>();
+        let (header_end, trailer_start) = match (descendants.first(), descendants.last()) {
+            (Some(first), Some(last)) => (first.start, last.end),
+            _ => (span.end, span.end),
+        };
+        let failed = failure.as_ref().filter(|f| f.span == i);
+
+        if span.start < header_end || (failed.is_some() && descendants.is_empty()) {
+            let note = match failed {
+                Some(f) if descendants.is_empty() || f.offset <= header_end => error_note(f),
+                _ => describe(bytes, span, header_end, descendants.is_empty()),
+            };
+            write_line(
+                &mut out,
+                bytes,
+                span.start..header_end,
+                &span.path,
+                &note,
+                failed,
+            );
+        }
+        if trailer_start < span.end {
+            let note = match failed {
+                Some(f) => error_note(f),
+                None => "end".to_string(),
+            };
+            trailers.push(Trailer {
+                range: trailer_start..span.end,
+                path: &span.path,
+                note,
+                failed,
+            });
+        }
+    }
+    while let Some(trailer) = trailers.pop() {
+        trailer.write(&mut out, bytes);
+    }
+    out
+}
+
+// the bytes of a node after its last descendant, e.g. the terminator of a vector
+struct Trailer<'a> {
+    range: std::ops::Range<usize>,
+    path: &'a str,
+    note: String,
+    failed: Option<&'a Failure>,
+}
+
+impl Trailer<'_> {
+    fn write(self, out: &mut String, bytes: &[u8]) {
+        write_line(out, bytes, self.range, self.path, &self.note, self.failed);
+    }
+}
+
+fn is_descendant(path: &str, ancestor: &str) -> bool {
+    path.len() > ancestor.len()
+        && path.starts_with(ancestor)
+        && path[ancestor.len()..].starts_with('/')
+}
+
+fn error_note(failure: &Failure) -> String {
+    format!("error: {}", failure.error)
+}
+
+// what the own bytes of a node decode to
+fn describe(bytes: &[u8], span: &Span, header_end: usize, is_leaf: bool) -> String {
+    let mut rule = match span.rule {
+        Some(rule) => rule,
+        None => return String::new(),
+    };
+    while let BinProtRule::Reference(RuleRef::Resolved(payload)) = rule {
+        rule = &payload.ref_rule;
+    }
+    let mut header = &bytes[span.start..header_end];
+    match rule {
+        BinProtRule::Option(_) => match header.first() {
+            Some(0x00) => "None".to_string(),
+            _ => "Some".to_string(),
+        },
+        BinProtRule::List(_) => match header.bin_read_nat0::<u64>() {
+            Ok(len) => format!("length {}", len),
+            Err(e) => format!("error: {}", e),
+        },
+        BinProtRule::Sum(summands) => header
+            .first()
+            .and_then(|b| summands.get(*b as usize))
+            .map(|s| s.ctor_name.to_string())
+            .unwrap_or_default(),
+        _ if is_leaf => match Leaf::of(rule) {
+            Some(leaf) => match leaf.read(&mut &bytes[span.start..span.end]) {
+                Ok(value) => value.to_sexp().to_string(),
+                Err(e) => format!("error: {}", e),
+            },
+            None => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+fn write_line(
+    out: &mut String,
+    bytes: &[u8],
+    range: std::ops::Range<usize>,
+    path: &str,
+    note: &str,
+    failure: Option<&Failure>,
+) {
+    let path = if path.is_empty() { "/" } else { path };
+    // a byte was rejected unless the input ran out
+    let rejected = failure
+        .filter(|f| !matches!(f.error, Error::Io(_)) && f.offset > 0)
+        .map(|f| f.offset - 1);
+    let chunks = bytes[range.start..range.end.min(bytes.len())]
+        .chunks(BYTES_PER_LINE)
+        .enumerate();
+    let mut wrote = false;
+    for (n, chunk) in chunks {
+        let chunk_start = range.start + n * BYTES_PER_LINE;
+        let padding = BYTES_PER_LINE - chunk.len();
+        for _ in 0..padding {
+            out.push_str(".. ");
+        }
+        for b in chunk.iter().rev() {
+            write!(out, "{:02x} ", b).unwrap();
+        }
+        match n {
+            0 => writeln!(out, "-> {} {}", path, note).unwrap(),
+            _ => writeln!(out, "->").unwrap(),
+        }
+        if let Some(offset) =
+            rejected.filter(|o| (chunk_start..chunk_start + chunk.len()).contains(o))
+        {
+            let column = padding + (chunk.len() - 1 - (offset - chunk_start));
+            writeln!(out, "{}^^", " ".repeat(column * 3)).unwrap();
+        }
+        wrote = true;
+    }
+    if !wrote {
+        // nothing could be read for this node
+        for _ in 0..BYTES_PER_LINE {
+            out.push_str(".. ");
+        }
+        writeln!(out, "-> {} {}", path, note).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULE: &str = r#"
+    ["Record", [
+      { "field_name": "first", "field_rule": ["Option", ["Tuple", [["Int"], ["Bool"]]]] },
+      { "field_name": "second", "field_rule": ["Sum", [
+        { "ctor_name": "One", "index": 0, "ctor_args": [] },
+        { "ctor_name": "Two", "index": 1, "ctor_args": [["List", ["String"]]] }
+      ]] }
+    ]]
+    "#;
+
+    const EXPECTED: &str = r#"
+.. .. .. .. .. .. .. 01 -> /first Some
+.. .. .. .. .. 01 00 fe -> /first/0 256
+.. .. .. .. .. .. .. 01 -> /first/1 true
+.. .. .. .. .. .. .. 01 -> /second Two
+.. .. .. .. .. .. .. 01 -> /second/Two length 1
+.. .. .. .. 63 62 61 03 -> /second/Two/0 abc
+"#;
+
+    const EXPECTED_FAILURE: &str = r#"
+.. .. .. .. .. .. .. 01 -> /first Some
+.. .. .. .. .. .. .. 05 -> /first/0 5
+.. .. .. .. .. .. .. 02 -> /first/1 error: Invalid byte for deserializing a bool or option. Expected one of: [0, 1], found: 2
+                     ^^
+"#;
+
+    #[test]
+    fn dumps_message() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let bytes = [
+            0x01, 0xfe, 0x00, 0x01, 0x01, 0x01, 0x01, 0x03, b'a', b'b', b'c',
+        ];
+        assert_eq!(format!("\n{}", hex_dump(&bytes, &rule)), EXPECTED);
+    }
+
+    #[test]
+    fn marks_failing_byte() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let bytes = [0x01, 0x05, 0x02, 0x00];
+        assert_eq!(format!("\n{}", hex_dump(&bytes, &rule)), EXPECTED_FAILURE);
+    }
+
+    #[test]
+    fn dumps_vector_terminator_after_elements() {
+        let rule: BinProtRule = serde_json::from_str(
+            r#"["Record", [
+              { "field_name": "items", "field_rule": ["Reference", ["Resolved", {
+                "source_type_decl": "Pickles_types.Vector.Vector2.t",
+                "source_module_path": "Pickles_type.Vector.Vector2",
+                "ref_rule": ["Custom", [["Bool"]]]
+              }]] },
+              { "field_name": "last", "field_rule": ["Bool"] }
+            ]]"#,
+        )
+        .unwrap();
+        let bytes = [0x01, 0x00, 0x00, 0x01];
+        assert_eq!(
+            format!("\n{}", hex_dump(&bytes, &rule)),
+            r#"
+.. .. .. .. .. .. .. 01 -> /items/0 true
+.. .. .. .. .. .. .. 00 -> /items/1 false
+.. .. .. .. .. .. .. 00 -> /items end
+.. .. .. .. .. .. .. 01 -> /last true
+"#
+        );
+    }
+}
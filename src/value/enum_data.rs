@@ -5,9 +5,12 @@ use serde::{Deserialize, Serialize};
 
 /// This is the data that needs to be serialized and passed
 /// to the serde deserializer so that the value::Sum can know
-/// its variant name and index as described in the layout
+/// its variant name and index as described in the layout.
+/// The number of constructor arguments is also passed so the
+/// payload can be read as a unit, single value or tuple
 #[derive(Serialize, Deserialize)]
 pub struct EnumData {
     pub index: u8,
     pub name: String,
+    pub arity: usize,
 }
@@ -1,10 +1,14 @@
 use super::Value;
 use std::ops;
+use std::sync::Arc;
 
 /// A type that can be used to index into a `bin_prot::Value`.
 pub trait Index {
     /// Return None if the key is not already in the array or object.
     fn index_into<'v>(&self, v: &'v Value) -> Option<&'v Value>;
+
+    /// Same as `index_into` but returns a mutable reference
+    fn index_into_mut<'v>(&self, v: &'v mut Value) -> Option<&'v mut Value>;
 }
 
 // Numeric indexing, only compatible with List and Tuple (or sum types containing either of these)
@@ -12,22 +16,57 @@ impl Index for usize {
     fn index_into<'v>(&self, v: &'v Value) -> Option<&'v Value> {
         match *v {
             Value::List(ref vec) | Value::Tuple(ref vec) => vec.get(*self),
-            Value::Sum { ref value, .. } => match **value {
+            Value::Sum {
+                value: Some(ref value),
+                ..
+            } => match **value {
                 Value::List(ref vec) | Value::Tuple(ref vec) => vec.get(*self),
                 _ => None,
             },
             _ => None,
         }
     }
+
+    fn index_into_mut<'v>(&self, v: &'v mut Value) -> Option<&'v mut Value> {
+        match *v {
+            Value::List(ref mut vec) | Value::Tuple(ref mut vec) => vec.get_mut(*self),
+            Value::Sum {
+                value: Some(ref mut value),
+                ..
+            } => match **value {
+                Value::List(ref mut vec) | Value::Tuple(ref mut vec) => vec.get_mut(*self),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 }
 
 // String indexing. Only compatible with Record (or sum types containg a record)
+// Records are stored as an ordered list of fields so this is a linear search
 impl Index for str {
     fn index_into<'v>(&self, v: &'v Value) -> Option<&'v Value> {
         match *v {
-            Value::Record(ref map) => map.get(self),
-            Value::Sum { ref value, .. } => match **value {
-                Value::Record(ref map) => map.get(self),
+            Value::Record(ref fields) => get_field(fields, self),
+            Value::Sum {
+                value: Some(ref value),
+                ..
+            } => match **value {
+                Value::Record(ref fields) => get_field(fields, self),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn index_into_mut<'v>(&self, v: &'v mut Value) -> Option<&'v mut Value> {
+        match *v {
+            Value::Record(ref mut fields) => get_field_mut(fields, self),
+            Value::Sum {
+                value: Some(ref mut value),
+                ..
+            } => match **value {
+                Value::Record(ref mut fields) => get_field_mut(fields, self),
                 _ => None,
             },
             _ => None,
@@ -35,19 +74,38 @@ impl Index for str {
     }
 }
 
+fn get_field<'v>(fields: &'v [(Arc<str>, Value)], name: &str) -> Option<&'v Value> {
+    fields.iter().find(|(k, _)| &**k == name).map(|(_, v)| v)
+}
+
+fn get_field_mut<'v>(fields: &'v mut [(Arc<str>, Value)], name: &str) -> Option<&'v mut Value> {
+    fields
+        .iter_mut()
+        .find(|(k, _)| &**k == name)
+        .map(|(_, v)| v)
+}
+
 impl Index for String {
     fn index_into<'v>(&self, v: &'v Value) -> Option<&'v Value> {
         self[..].index_into(v)
     }
+
+    fn index_into_mut<'v>(&self, v: &'v mut Value) -> Option<&'v mut Value> {
+        self[..].index_into_mut(v)
+    }
 }
 
-impl<'a, T> Index for &'a T
+impl<T> Index for &T
 where
     T: ?Sized + Index,
 {
     fn index_into<'v>(&self, v: &'v Value) -> Option<&'v Value> {
         (**self).index_into(v)
     }
+
+    fn index_into_mut<'v>(&self, v: &'v mut Value) -> Option<&'v mut Value> {
+        (**self).index_into_mut(v)
+    }
 }
 
 impl<I> ops::Index<I> for Value
@@ -65,10 +123,22 @@ where
     }
 }
 
+impl<I> ops::IndexMut<I> for Value
+where
+    I: Index + std::fmt::Display,
+{
+    // Same as ops::Index, panics when indexing a value that doesn't exist
+    fn index_mut(&mut self, index: I) -> &mut Value {
+        if index.index_into(self).is_none() {
+            panic!("No value for index: {}", index)
+        }
+        index.index_into_mut(self).unwrap()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashMap;
 
     #[test]
     fn index_into_list() {
@@ -87,9 +157,7 @@ mod tests {
 
     #[test]
     fn index_into_record() {
-        let mut inner = HashMap::new();
-        inner.insert("one".to_string(), Value::Int(1));
-        inner.insert("two".to_string(), Value::Int(2));
+        let inner = vec![("one".into(), Value::Int(1)), ("two".into(), Value::Int(2))];
         let val = Value::Record(inner);
         assert_eq!(val["one"], Value::Int(1));
         assert_eq!(val["two"], Value::Int(2));
@@ -98,9 +166,7 @@ mod tests {
     #[test]
     #[should_panic(expected = "No value for index: missing")]
     fn no_value_for_key_panics() {
-        let mut inner = HashMap::new();
-        inner.insert("one".to_string(), Value::Int(1));
-        inner.insert("two".to_string(), Value::Int(2));
+        let inner = vec![("one".into(), Value::Int(1)), ("two".into(), Value::Int(2))];
         let val = Value::Record(inner);
         let _ = val["missing"];
     }
@@ -108,13 +174,13 @@ mod tests {
     #[test]
     fn index_into_tuple_variants() {
         let val = Value::Sum {
-            name: "variant A".to_string(),
+            name: "variant A".into(),
             index: 0,
-            value: Box::new(Value::List(vec![
+            value: Some(Box::new(Value::List(vec![
                 Value::Int(1),
                 Value::Int(2),
                 Value::Int(3),
-            ])),
+            ]))),
         };
         assert_eq!(val[0], Value::Int(1));
         assert_eq!(val[1], Value::Int(2));
@@ -123,14 +189,12 @@ mod tests {
 
     #[test]
     fn index_into_record_variants() {
-        let mut inner = HashMap::new();
-        inner.insert("one".to_string(), Value::Int(1));
-        inner.insert("two".to_string(), Value::Int(2));
+        let inner = vec![("one".into(), Value::Int(1)), ("two".into(), Value::Int(2))];
 
         let val = Value::Sum {
-            name: "variant A".to_string(),
+            name: "variant A".into(),
             index: 0,
-            value: Box::new(Value::Record(inner)),
+            value: Some(Box::new(Value::Record(inner))),
         };
         assert_eq!(val["one"], Value::Int(1));
         assert_eq!(val["two"], Value::Int(2));
@@ -138,16 +202,57 @@ mod tests {
 
     #[test]
     fn nested_indexing() {
-        let mut inner = HashMap::new();
-        inner.insert("B".to_string(), Value::Int(1));
-        let val = Value::Record(inner);
-
-        let mut outer = HashMap::new();
-        outer.insert("A".to_string(), val);
+        let inner = Value::Record(vec![("B".into(), Value::Int(1))]);
+        let outer = Value::Record(vec![("A".into(), inner)]);
 
         assert_eq!(outer["A"]["B"], Value::Int(1));
     }
 
+    #[test]
+    fn get_returns_none_for_missing_index() {
+        let val = Value::Record(vec![("one".into(), Value::Int(1))]);
+        assert_eq!(val.get("one"), Some(&Value::Int(1)));
+        assert_eq!(val.get("two"), None);
+        assert_eq!(val.get(0), None);
+    }
+
+    #[test]
+    fn can_mutate_through_index() {
+        let mut val = Value::Record(vec![(
+            "list".into(),
+            Value::List(vec![Value::Int(1), Value::Int(2)]),
+        )]);
+        val["list"][1] = Value::Int(5);
+        *val.get_mut("list").unwrap().get_mut(0).unwrap() = Value::Int(4);
+        assert_eq!(val["list"], Value::List(vec![Value::Int(4), Value::Int(5)]));
+    }
+
+    #[test]
+    #[should_panic(expected = "No value for index: missing")]
+    fn index_mut_missing_key_panics() {
+        let mut val = Value::Record(vec![]);
+        val["missing"] = Value::Unit;
+    }
+
+    #[test]
+    fn pointer_steps_through_sums_and_options() {
+        let val = Value::Record(vec![(
+            "body".into(),
+            Value::Option(Some(Box::new(Value::Sum {
+                name: "V1".into(),
+                index: 0,
+                value: Some(Box::new(Value::Record(vec![(
+                    "items".into(),
+                    Value::List(vec![Value::Bool(true)]),
+                )]))),
+            }))),
+        )]);
+        assert_eq!(val.pointer("/body/items/0"), Some(&Value::Bool(true)));
+        assert_eq!(val.pointer(""), Some(&val));
+        assert_eq!(val.pointer("/body/items/1"), None);
+        assert_eq!(val.pointer("body"), None);
+    }
+
     #[test]
     fn can_access_option_inner() {
         let val = Value::Option(None);
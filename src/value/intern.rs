@@ -0,0 +1,65 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sharing of record field and constructor names between Values.
+//!
+//! A decoded message often contains thousands of records of the same type. Rather than each
+//! record owning a copy of its field names, the names are interned as a layout is read and
+//! Values built by walking the layout (e.g. with a `DecodePlan`) clone them from it. Values built
+//! by the serde visitor look the names up with `shared` instead.
+//!
+//! Only layout names are interned, so the set is bounded by the layouts in use and the interned
+//! names are kept for the life of the thread. A layout read on another thread shares nothing
+//! with the Values decoded on this one.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+thread_local! {
+    static NAMES: RefCell<HashSet<Arc<str>>> = RefCell::new(HashSet::new());
+}
+
+/// Returns a shared copy of `name`, allocating it only the first time it is seen on this thread
+pub(crate) fn intern(name: &str) -> Arc<str> {
+    NAMES.with(|names| {
+        let mut names = names.borrow_mut();
+        match names.get(name) {
+            Some(shared) => shared.clone(),
+            None => {
+                let shared: Arc<str> = Arc::from(name);
+                names.insert(shared.clone());
+                shared
+            }
+        }
+    })
+}
+
+/// Returns the interned copy of `name` if there is one, otherwise a new allocation that is not
+/// interned. Used where names may not come from a layout so the set stays bounded
+pub(crate) fn shared(name: &str) -> Arc<str> {
+    NAMES
+        .with(|names| names.borrow().get(name).cloned())
+        .unwrap_or_else(|| Arc::from(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interned_names_share_an_allocation() {
+        let a = intern("receiver_pk");
+        let b = intern(&String::from("receiver_pk"));
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &intern("fee_payer_pk")));
+    }
+
+    #[test]
+    fn shared_names_are_not_interned() {
+        let a = intern("fee_excess");
+        assert!(Arc::ptr_eq(&a, &shared("fee_excess")));
+        let b = shared("not_in_any_layout");
+        assert!(!Arc::ptr_eq(&b, &shared("not_in_any_layout")));
+    }
+}
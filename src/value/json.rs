@@ -0,0 +1,155 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Export of Values as JSON.
+//!
+//! Two styles are supported. `Yojson` matches the output of OCaml's `ppx_deriving_yojson`
+//! so decoded messages can be compared against what the OCaml node produces. `Plain` matches
+//! what serde_json produces for an equivalent Rust type deriving Serialize.
+
+use serde_json::{json, Map, Number, Value as JsonValue};
+
+use super::Value;
+
+/// The convention used when converting a Value to JSON
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JsonStyle {
+    /// The `ppx_deriving_yojson` convention. Constructors are lists of the form
+    /// `["Ctor", args...]` (the same ListTaggedEnum convention used by layout files),
+    /// options are `null` or the value and unit is `null`
+    Yojson,
+    /// The serde externally tagged convention. Constant constructors are `"Ctor"` and
+    /// constructors with arguments are `{"Ctor": payload}`
+    Plain,
+}
+
+impl Value {
+    /// Convert this value to JSON using the given style.
+    /// Records become objects keyed by field name in both styles. Strings become JSON strings,
+    /// or an array of their bytes if they are not valid utf-8 (as serde_json does for a Value).
+    /// A hex string is not used for those, as it could not be told apart from a utf-8 string
+    /// that happens to look like hex when the JSON is encoded back with `json_to_bin_prot`
+    pub fn to_json_with(&self, style: JsonStyle) -> JsonValue {
+        match self {
+            Value::Unit => JsonValue::Null,
+            Value::Nat0(n) => json!(n),
+            Value::Bool(b) => json!(b),
+            Value::String(bytes) => match std::str::from_utf8(bytes) {
+                Ok(s) => json!(s),
+                Err(_) => json!(bytes),
+            },
+            Value::Char(c) => json!(c.to_string()),
+            Value::Int(i) => json!(i),
+            // non-finite floats cannot be represented in JSON
+            Value::Float(f) => Number::from_f64(*f)
+                .map(JsonValue::Number)
+                .unwrap_or(JsonValue::Null),
+            Value::Option(None) => JsonValue::Null,
+            Value::Option(Some(v)) => v.to_json_with(style),
+            Value::Record(fields) => JsonValue::Object(
+                fields
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_json_with(style)))
+                    .collect::<Map<_, _>>(),
+            ),
+            Value::Tuple(items) | Value::List(items) => {
+                JsonValue::Array(items.iter().map(|v| v.to_json_with(style)).collect())
+            }
+            Value::Sum { name, value, .. } => match style {
+                JsonStyle::Yojson => {
+                    let mut list = vec![json!(&**name)];
+                    match value.as_deref() {
+                        None => {}
+                        Some(Value::Tuple(args)) => {
+                            list.extend(args.iter().map(|v| v.to_json_with(style)))
+                        }
+                        Some(v) => list.push(v.to_json_with(style)),
+                    }
+                    JsonValue::Array(list)
+                }
+                JsonStyle::Plain => match value.as_deref() {
+                    None => json!(&**name),
+                    Some(v) => json!({ &**name: v.to_json_with(style) }),
+                },
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Value {
+        Value::Record(vec![
+            ("height".into(), Value::Int(3)),
+            (
+                "body".into(),
+                Value::Option(Some(Box::new(Value::Sum {
+                    name: "Pair".into(),
+                    index: 1,
+                    value: Some(Box::new(Value::Tuple(vec![
+                        Value::Int(-1),
+                        Value::Bool(true),
+                    ]))),
+                }))),
+            ),
+            ("parent".into(), Value::Option(None)),
+            (
+                "kind".into(),
+                Value::Sum {
+                    name: "Empty".into(),
+                    index: 0,
+                    value: None,
+                },
+            ),
+            ("hash".into(), Value::String(vec![0xff, 0x01])),
+            ("memo".into(), Value::String(b"hi".to_vec())),
+        ])
+    }
+
+    #[test]
+    fn yojson_style() {
+        assert_eq!(
+            example().to_json_with(JsonStyle::Yojson),
+            json!({
+                "height": 3,
+                "body": ["Pair", -1, true],
+                "parent": null,
+                "kind": ["Empty"],
+                "hash": [255, 1],
+                "memo": "hi"
+            })
+        );
+    }
+
+    #[test]
+    fn plain_style() {
+        assert_eq!(
+            example().to_json_with(JsonStyle::Plain),
+            json!({
+                "height": 3,
+                "body": { "Pair": [-1, true] },
+                "parent": null,
+                "kind": "Empty",
+                "hash": [255, 1],
+                "memo": "hi"
+            })
+        );
+    }
+
+    #[test]
+    fn binary_strings_match_serde_json() {
+        let binary = Value::String(vec![0xff, 0x01]);
+        assert_eq!(
+            binary.to_json_with(JsonStyle::Plain),
+            serde_json::to_value(&binary).unwrap()
+        );
+    }
+
+    #[test]
+    fn record_field_order_is_preserved() {
+        let json = serde_json::to_string(&example().to_json_with(JsonStyle::Yojson)).unwrap();
+        assert!(json.starts_with(r#"{"height":3,"body":"#));
+    }
+}
@@ -2,20 +2,28 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use core::convert::TryFrom;
+use std::sync::Arc;
 
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+
+use super::intern::intern;
 use serde_json::from_value;
 
 mod list_tagged_enum;
 mod traverse;
+mod walk;
 
 pub use traverse::{BinProtRuleIterator, BranchingIterator};
+pub(crate) use walk::{
+    capacity_for, custom_vector, read_vector_terminator, select_summand, summand_index, ModulePath,
+    Walker,
+};
 
 use list_tagged_enum::ListTaggedEnum;
 
 /// The main top level type for a layout file.
 /// Parse into this from json
-#[derive(Deserialize, Debug)]
+#[derive(Clone, Deserialize, Debug)]
 pub struct Layout {
     pub layout_loc: String,
     pub version_opt: Option<i32>,
@@ -24,7 +32,9 @@ pub struct Layout {
     pub bin_prot_rule: BinProtRule,
 }
 
-/// Recursively defined BinProtRule is how the type tree is constructed
+/// Recursively defined BinProtRule is how the type tree is constructed.
+/// Children are reference counted so cloning a rule, as happens at each step of a
+/// traversal, is cheap and does not copy the subtree
 #[derive(Clone, Deserialize, Debug)]
 #[serde(try_from = "ListTaggedEnum")]
 pub enum BinProtRule {
@@ -38,26 +48,26 @@ pub enum BinProtRule {
     Int64,
     NativeInt,
     Float,
-    Option(Box<BinProtRule>),
-    Record(Vec<RecordField>), // records/structs
-    Tuple(Vec<BinProtRule>),
-    Sum(Vec<Summand>), // sum types/enums
-    Polyvar(Vec<Polyvar>),
-    List(Box<BinProtRule>),
+    Option(Arc<BinProtRule>),
+    Record(Arc<[RecordField]>), // records/structs
+    Tuple(Arc<[BinProtRule]>),
+    Sum(Arc<[Summand]>), // sum types/enums
+    Polyvar(Arc<[Polyvar]>),
+    List(Arc<BinProtRule>),
     Hashtable(HashTblEntry),
-    Vec(usize, Box<BinProtRule>),
+    Vec(usize, Arc<BinProtRule>),
     Bigstring,
     // //  track indirections for debugging *),
     Reference(RuleRef),
     TypeVar(String),
     // //  inside a recursive type, list of type parameters *),
-    SelfReference(Vec<BinProtRule>),
+    SelfReference(Arc<[BinProtRule]>),
     // //  parameterized type: 'a t = ... *),
-    TypeAbstraction(Vec<String>, Box<BinProtRule>),
+    TypeAbstraction(Vec<String>, Arc<BinProtRule>),
     // //  recursive parameterized type with bindings *),
-    TypeClosure(Vec<(String, BinProtRule)>, Box<BinProtRule>),
-    Custom(Vec<BinProtRule>),
-    CustomForPath(String, Vec<BinProtRule>), // does not occur in source files, used in traverse
+    TypeClosure(Vec<(String, BinProtRule)>, Arc<BinProtRule>),
+    Custom(Arc<[BinProtRule]>),
+    CustomForPath(String, Arc<[BinProtRule]>), // does not occur in source files, used in traverse
 }
 
 // required due to the strange enum encoding used by yojson (see list_tagged_enum.rs)
@@ -130,23 +140,44 @@ impl TryFrom<ListTaggedEnum> for BinProtRule {
     }
 }
 
+/// Length of the fixed size Pickles vector types which have a custom encoding,
+/// given the module path of the reference containing the Custom rule (see traverse.rs)
+pub(crate) fn custom_vector_len(module_path: &str) -> Option<usize> {
+    match module_path {
+        // the missing 's' on 'types' is intentional due to a bug in the layout producing code
+        "Pickles_type.Vector.Vector2" => Some(2),
+        "Pickles_types.Vector.Vector4" => Some(4),
+        "Pickles_types.Vector.Vector8" => Some(8),
+        "Pickles_types.Vector.Vector17" => Some(17),
+        "Pickles_types.Vector.Vector18" => Some(18),
+        _ => None,
+    }
+}
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct RecordField {
-    pub field_name: String,
+    #[serde(deserialize_with = "intern_name")]
+    pub field_name: Arc<str>,
     pub field_rule: BinProtRule,
 }
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct Summand {
-    pub ctor_name: String,
+    #[serde(deserialize_with = "intern_name")]
+    pub ctor_name: Arc<str>,
     pub index: i32,
-    pub ctor_args: Vec<BinProtRule>,
+    pub ctor_args: Arc<[BinProtRule]>,
+}
+
+// Names are interned as the layout is read so Values decoded with it share them
+fn intern_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<str>, D::Error> {
+    String::deserialize(deserializer).map(|name| intern(&name))
 }
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct HashTblEntry {
-    key_rule: Box<BinProtRule>,
-    value_rule: Box<BinProtRule>,
+    pub key_rule: Arc<BinProtRule>,
+    pub value_rule: Arc<BinProtRule>,
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -158,9 +189,9 @@ pub enum Polyvar {
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct TaggedPolyvar {
-    polyvar_name: String,
-    hash: i32,
-    polyvar_args: Vec<BinProtRule>,
+    pub polyvar_name: String,
+    pub hash: i32,
+    pub polyvar_args: Arc<[BinProtRule]>,
 }
 
 impl TryFrom<ListTaggedEnum> for Polyvar {
@@ -182,24 +213,25 @@ impl TryFrom<ListTaggedEnum> for Polyvar {
 #[derive(Clone, Deserialize, Debug)]
 #[serde(try_from = "ListTaggedEnum")]
 pub enum RuleRef {
-    Unresolved(UnresolvedPayload),
-    Resolved(ResolvedPayload),
+    Unresolved(Arc<UnresolvedPayload>),
+    Resolved(Arc<ResolvedPayload>),
 }
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct UnresolvedPayload {
-    params: Vec<BinProtRule>,
-    layout_id: String, // what is longident?
+    pub params: Vec<BinProtRule>,
+    pub layout_id: String, // what is longident?
 }
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct ResolvedPayload {
-    source_type_decl: String,
+    pub source_type_decl: String,
     #[serde(default)]
-    source_module_path: String,
+    pub source_module_path: String,
+    // This is present only in later versions of the type layouts. Probably don't trust its value
     #[serde(default)]
-    bin_io_derived: bool, // This is present only in later versions of the type layouts. Probably don't trust its value
-    ref_rule: Box<BinProtRule>,
+    pub bin_io_derived: bool,
+    pub ref_rule: Arc<BinProtRule>,
 }
 
 impl TryFrom<ListTaggedEnum> for RuleRef {
@@ -21,6 +21,8 @@
 //! Combined this allows parsing of types defined by the layout into loosely typed representations.
 //!
 
+use std::sync::Arc;
+
 use crate::value::layout::{BinProtRule, Polyvar, RuleRef};
 
 /// Implements a depth first search of the type tree
@@ -29,7 +31,7 @@ pub struct BinProtRuleIterator {
     stack: Vec<BinProtRule>, // regular stack to implement the DFS
     // Tree nodes can branch (only one child should be followed) rather than require traversal of all children
     // If that is the case the parent should add the children to the branch field and the next path will be taken from here rather than the stack
-    branch: Option<Vec<Vec<BinProtRule>>>,
+    branch: Option<Vec<Arc<[BinProtRule]>>>,
     current_module_path: Option<String>, // holds on to most recent path encountered in traverse
 }
 
@@ -61,30 +63,28 @@ impl BranchingIterator for BinProtRuleIterator {
                 match rule {
                     BinProtRule::Option(r) | BinProtRule::List(r) => {
                         // the code driving the iterator should call `repeat` if it encounters a list
-                        self.stack.push(*r);
+                        self.stack.push((*r).clone());
                     }
-                    BinProtRule::Record(mut rules) => {
+                    BinProtRule::Record(rules) => {
                         self.stack
-                            .extend(rules.drain(0..).map(|field| field.field_rule).rev());
+                            .extend(rules.iter().map(|field| field.field_rule.clone()).rev());
                     }
-                    BinProtRule::Tuple(mut rules) => {
-                        self.stack.extend(rules.drain(0..).rev());
+                    BinProtRule::Tuple(rules) => {
+                        self.stack.extend(rules.iter().cloned().rev());
                     }
                     BinProtRule::Sum(summands) => {
                         // don't add to the stack. Add to the branch field instead
                         // this must be resolved by calling `branch` before the iterator can continue
-                        self.branch = Some(summands.into_iter().map(|s| s.ctor_args).collect());
+                        self.branch = Some(summands.iter().map(|s| s.ctor_args.clone()).collect());
                     }
                     BinProtRule::Polyvar(polyvars) => {
                         // these are pretty much anonymous enum/sum types and should be handled the same way
                         self.branch = Some(
                             polyvars
-                                .into_iter()
+                                .iter()
                                 .map(|s| match s {
-                                    Polyvar::Tagged(pv) => pv.polyvar_args,
-                                    Polyvar::Inherited(rule) => {
-                                        vec![rule]
-                                    }
+                                    Polyvar::Tagged(pv) => pv.polyvar_args.clone(),
+                                    Polyvar::Inherited(rule) => Arc::from(vec![rule.clone()]),
                                 })
                                 .collect(),
                         );
@@ -94,8 +94,8 @@ impl BranchingIterator for BinProtRuleIterator {
                             unimplemented!();
                         }
                         RuleRef::Resolved(payload) => {
-                            self.stack.push(*payload.ref_rule);
-                            self.current_module_path = Some(payload.source_module_path);
+                            self.stack.push((*payload.ref_rule).clone());
+                            self.current_module_path = Some(payload.source_module_path.clone());
                         }
                     },
                     BinProtRule::String
@@ -132,11 +132,12 @@ impl BranchingIterator for BinProtRuleIterator {
             }
         }
 
-        if let Some(mut branches) = self.branch.take() {
+        if let Some(branches) = self.branch.take() {
             let s = branches
-                .get_mut(branch)
+                .get(branch)
                 .ok_or_else(|| "Invalid branch".to_string())?;
-            self.stack.extend(s.drain(0..));
+            // constructor arguments are pushed in reverse so they are visited in order
+            self.stack.extend(s.iter().cloned().rev());
             Ok(())
         } else {
             Err("Cannot branch at this location in the tree".to_string())
@@ -148,7 +149,7 @@ impl BinProtRuleIterator {
     // takes whatever is next on the stack and repeats it to it appears `reps` times
     pub fn repeat(&mut self, reps: usize) {
         if let Some(top) = self.stack.pop() {
-            self.stack.extend(std::iter::repeat(top).take(reps));
+            self.stack.extend(std::iter::repeat_n(top, reps));
         }
     }
 
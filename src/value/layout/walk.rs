@@ -0,0 +1,113 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pieces shared by everything that follows a layout through a message, e.g. skipping,
+//! decode plans and JSON encoding, so that they all agree on how a rule is encoded.
+
+use std::convert::TryFrom;
+use std::io::Read;
+
+use byteorder::ReadBytesExt;
+
+use super::{custom_vector_len, BinProtRule, ResolvedPayload, Summand};
+use crate::error::{Error, Result};
+
+/// The summand (or anything built from the summands in order) a variant byte refers to.
+/// Summands are selected by their position in the layout, the same as `BinProtRuleIterator::branch`
+pub(crate) fn select_summand<T>(summands: &[T], byte: u8) -> Result<&T> {
+    summands
+        .get(byte as usize)
+        .ok_or_else(|| Error::InvalidByte {
+            byte,
+            dtype: "variant index".to_string(),
+            allowed: (0..summands.len() as u8).collect(),
+        })
+}
+
+/// The summand with the given constructor name and the index stored for it in a `Value::Sum`.
+/// The index is its position in the layout, the inverse of `select_summand`
+pub(crate) fn summand_index<'s>(summands: &'s [Summand], name: &[u8]) -> Option<(u8, &'s Summand)> {
+    summands
+        .iter()
+        .enumerate()
+        .find(|(_, s)| s.ctor_name.as_bytes() == name)
+        .and_then(|(i, s)| Some((u8::try_from(i).ok()?, s)))
+}
+
+/// The capacity to reserve for `len` children when the length was read from the input.
+/// Capped so that a corrupt length can't cause a huge allocation
+pub(crate) fn capacity_for(len: usize) -> usize {
+    len.min(1024)
+}
+
+/// The length and element rule of a custom rule inside a reference with the given module path.
+/// Only the fixed size Pickles vectors are supported
+pub(crate) fn custom_vector<'r>(
+    module_path: &str,
+    rules: &'r [BinProtRule],
+) -> Result<(usize, &'r BinProtRule)> {
+    match (custom_vector_len(module_path), rules.first()) {
+        (Some(len), Some(element)) => Ok((len, element)),
+        _ => Err(Error::Custom {
+            message: format!("No strategy for custom type {}", module_path),
+        }),
+    }
+}
+
+/// Reads the zero byte that ends a custom vector
+pub(crate) fn read_vector_terminator<R: Read>(rdr: &mut R) -> Result<()> {
+    match rdr.read_u8()? {
+        0x00 => Ok(()),
+        byte => Err(Error::InvalidByte {
+            byte,
+            dtype: "vector terminator".to_string(),
+            allowed: vec![0x00],
+        }),
+    }
+}
+
+/// The module path of the innermost reference being walked through,
+/// which is what identifies the encoding of a custom rule (see traverse.rs)
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ModulePath(Option<String>);
+
+impl ModulePath {
+    pub(crate) fn as_str(&self) -> &str {
+        self.0.as_deref().unwrap_or_default()
+    }
+
+    /// See `custom_vector`
+    pub(crate) fn custom_vector<'r>(
+        &self,
+        rules: &'r [BinProtRule],
+    ) -> Result<(usize, &'r BinProtRule)> {
+        custom_vector(self.as_str(), rules)
+    }
+}
+
+impl From<&str> for ModulePath {
+    fn from(module_path: &str) -> Self {
+        ModulePath(Some(module_path.to_string()))
+    }
+}
+
+/// Something that walks a layout and so has to keep track of the module path
+pub(crate) trait Walker: Sized {
+    fn module_path(&mut self) -> &mut ModulePath;
+
+    /// Applies `f` to the rule a reference resolves to, with the module path of the
+    /// reference for the duration of the call
+    fn within_reference<'r, T>(
+        &mut self,
+        payload: &'r ResolvedPayload,
+        f: impl FnOnce(&mut Self, &'r BinProtRule) -> T,
+    ) -> T {
+        let outer = self
+            .module_path()
+            .0
+            .replace(payload.source_module_path.clone());
+        let result = f(self, &payload.ref_rule);
+        self.module_path().0 = outer;
+        result
+    }
+}
@@ -0,0 +1,379 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! A view over an encoded buffer that decodes only the parts that are navigated to.
+//!
+//! A `LazyValue` is a position in a buffer together with the layout rule of the value encoded
+//! there. Moving to a record field, list element or constructor argument skips over the
+//! preceding siblings (see `skip`) to find where the child starts. The start of every child is
+//! worked out the first time any child is requested and cached, so repeated access into the same
+//! node is constant time. Nothing is copied out of the buffer until `decode` is called.
+
+use std::cell::{Ref, RefCell};
+
+use serde::de::DeserializeOwned;
+
+use super::skip::Skipper;
+use super::Value;
+use crate::error::{Error, Result};
+use crate::value::layout::{
+    capacity_for, custom_vector, read_vector_terminator, select_summand, BinProtRule, Layout,
+    ModulePath, RecordField, RuleRef, Summand,
+};
+use crate::{Deserializer, ReadBinProtExt};
+
+/// A lazily decoded value borrowed from an encoded buffer
+#[derive(Clone, Debug)]
+pub struct LazyValue<'a> {
+    bytes: &'a [u8], // starts at this value but may continue past its end
+    rule: &'a BinProtRule,
+    module_path: ModulePath, // of the innermost reference enclosing this value
+    offsets: RefCell<Option<Vec<usize>>>, // start of each child followed by the end of the last
+}
+
+// the rules of the children of a node
+enum Children<'a> {
+    Fields(&'a [RecordField]),
+    Items(&'a [BinProtRule]),
+    Repeat(&'a BinProtRule),
+}
+
+impl<'a> Children<'a> {
+    fn rule(&self, i: usize) -> &'a BinProtRule {
+        match self {
+            Children::Fields(fields) => &fields[i].field_rule,
+            Children::Items(rules) => &rules[i],
+            Children::Repeat(rule) => rule,
+        }
+    }
+}
+
+impl<'a> LazyValue<'a> {
+    /// View the value encoded at the start of `bytes` using `rule`
+    pub fn new(bytes: &'a [u8], rule: &'a BinProtRule) -> Self {
+        Self::within(bytes, rule, ModulePath::default())
+    }
+
+    fn within(bytes: &'a [u8], rule: &'a BinProtRule, module_path: ModulePath) -> Self {
+        Self {
+            bytes,
+            rule,
+            module_path,
+            offsets: RefCell::new(None),
+        }
+    }
+
+    pub fn from_layout(bytes: &'a [u8], layout: &'a Layout) -> Self {
+        Self::new(bytes, &layout.bin_prot_rule)
+    }
+
+    /// The field of a record
+    pub fn field(&self, name: &str) -> Result<LazyValue<'a>> {
+        match self.resolved().0 {
+            BinProtRule::Record(fields) => {
+                let i = fields
+                    .iter()
+                    .position(|f| *f.field_name == *name)
+                    .ok_or_else(|| Error::Custom {
+                        message: format!("No field {} in record", name),
+                    })?;
+                self.get(i)
+            }
+            r => Err(Error::Custom {
+                message: format!("Cannot get field {} of {:?}", name, r),
+            }),
+        }
+    }
+
+    /// The i-th child. This is the element of a list or vector, the item of a tuple,
+    /// the field of a record in declaration order or the argument of a constructor
+    pub fn get(&self, i: usize) -> Result<LazyValue<'a>> {
+        let (_, children) = self.children()?;
+        let offsets = self.offsets()?;
+        if i + 1 >= offsets.len() {
+            return Err(Error::Custom {
+                message: format!(
+                    "Index {} out of range for {} children",
+                    i,
+                    offsets.len() - 1
+                ),
+            });
+        }
+        Ok(LazyValue::within(
+            &self.bytes[offsets[i]..],
+            children.rule(i),
+            ModulePath::from(self.resolved().1),
+        ))
+    }
+
+    /// The number of children (see `get`)
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.children()?.0)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// The contents of an option
+    pub fn option(&self) -> Result<Option<LazyValue<'a>>> {
+        match self.resolved().0 {
+            BinProtRule::Option(_) => match self.len()? {
+                0 => Ok(None),
+                _ => self.get(0).map(Some),
+            },
+            r => Err(Error::Custom {
+                message: format!("Expected an option, found {:?}", r),
+            }),
+        }
+    }
+
+    /// The constructor name and index of a sum. The arguments are its children
+    pub fn variant(&self) -> Result<(&'a str, u8)> {
+        match self.resolved().0 {
+            BinProtRule::Sum(summands) => {
+                let index = self.bytes.first().copied().unwrap_or_default();
+                let summand = self.summand(summands)?;
+                Ok((&summand.ctor_name, index))
+            }
+            r => Err(Error::Custom {
+                message: format!("Expected a sum, found {:?}", r),
+            }),
+        }
+    }
+
+    /// The encoded bytes of this value
+    pub fn as_bytes(&self) -> Result<&'a [u8]> {
+        let len = match &*self.offsets.borrow() {
+            Some(offsets) => *offsets.last().unwrap(),
+            None => skip(self.bytes, self.rule, &self.module_path)?,
+        };
+        Ok(&self.bytes[..len])
+    }
+
+    /// Fully decode this value
+    pub fn decode(&self) -> Result<Value> {
+        self.decode_as()
+    }
+
+    /// Fully decode this value as any type implementing `Deserialize`
+    pub fn decode_as<T: DeserializeOwned>(&self) -> Result<T> {
+        let mut de = Deserializer::from_reader_with_layout(self.bytes, self.rule.clone())
+            .with_module_path(self.module_path.clone());
+        T::deserialize(&mut de)
+    }
+
+    // the rule with references followed along with the module path of the last reference
+    fn resolved(&self) -> (&'a BinProtRule, &str) {
+        let mut rule = self.rule;
+        let mut module_path = self.module_path.as_str();
+        while let BinProtRule::Reference(RuleRef::Resolved(payload)) = rule {
+            module_path = &payload.source_module_path;
+            rule = &payload.ref_rule;
+        }
+        (rule, module_path)
+    }
+
+    // the number of children and their rules
+    fn children(&self) -> Result<(usize, Children<'a>)> {
+        self.shape().map(|(len, _, children)| (len, children))
+    }
+
+    // as children but also with where the first child starts
+    fn shape(&self) -> Result<(usize, usize, Children<'a>)> {
+        let (rule, module_path) = self.resolved();
+        Ok(match rule {
+            BinProtRule::Record(fields) => (fields.len(), 0, Children::Fields(fields)),
+            BinProtRule::Tuple(rules) => (rules.len(), 0, Children::Items(rules)),
+            BinProtRule::List(r) => {
+                let mut rdr = self.bytes;
+                let len = rdr.bin_read_nat0::<u64>()? as usize;
+                (len, self.bytes.len() - rdr.len(), Children::Repeat(r))
+            }
+            BinProtRule::Option(r) => {
+                let mut rdr = self.bytes;
+                (rdr.bin_read_bool()? as usize, 1, Children::Repeat(r))
+            }
+            BinProtRule::Sum(summands) => {
+                let summand = self.summand(summands)?;
+                (
+                    summand.ctor_args.len(),
+                    1,
+                    Children::Items(&summand.ctor_args),
+                )
+            }
+            BinProtRule::Custom(rules) => {
+                let (len, element) = custom_vector(module_path, rules)?;
+                (len, 0, Children::Repeat(element))
+            }
+            r => {
+                return Err(Error::Custom {
+                    message: format!("{:?} has no children", r),
+                })
+            }
+        })
+    }
+
+    // computes the offsets of all children the first time they are needed
+    fn offsets(&self) -> Result<Ref<'_, Vec<usize>>> {
+        if self.offsets.borrow().is_none() {
+            let (len, start, children) = self.shape()?;
+            let module_path = ModulePath::from(self.resolved().1);
+            let mut offsets = Vec::with_capacity(capacity_for(len) + 1);
+            let mut offset = start;
+            for i in 0..len {
+                offsets.push(offset);
+                offset += skip(&self.bytes[offset..], children.rule(i), &module_path)?;
+            }
+            if let BinProtRule::Custom(_) = self.resolved().0 {
+                read_vector_terminator(&mut &self.bytes[offset..])?;
+                offset += 1;
+            }
+            offsets.push(offset);
+            *self.offsets.borrow_mut() = Some(offsets);
+        }
+        Ok(Ref::map(self.offsets.borrow(), |o| o.as_ref().unwrap()))
+    }
+
+    // the summand selected by the variant byte at the start of this value
+    fn summand(&self, summands: &'a [Summand]) -> Result<&'a Summand> {
+        let mut rdr = self.bytes;
+        select_summand(summands, rdr.bin_read_variant_index()?)
+    }
+}
+
+// skips a value as `skip::skip` does but within a reference with the given module path
+fn skip(bytes: &[u8], rule: &BinProtRule, module_path: &ModulePath) -> Result<usize> {
+    let mut skipper = Skipper::new(bytes);
+    skipper.module_path = module_path.clone();
+    skipper.skip(rule)?;
+    Ok(skipper.rdr.count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin_prot_value;
+
+    const RULE: &str = r#"
+    ["Record", [
+      { "field_name": "header", "field_rule": ["String"] },
+      { "field_name": "entries", "field_rule": ["List", ["Record", [
+        { "field_name": "key", "field_rule": ["Int"] },
+        { "field_name": "data", "field_rule": ["Option", ["Sum", [
+          { "ctor_name": "Empty", "index": 0, "ctor_args": [] },
+          { "ctor_name": "Pair", "index": 1, "ctor_args": [["String"], ["Bool"]] }
+        ]]] }
+      ]]] }
+    ]]
+    "#;
+
+    fn example_bytes() -> Vec<u8> {
+        let mut bytes = vec![0x03, b'h', b'd', b'r', 0x03]; // header: "hdr", 3 entries
+        bytes.extend_from_slice(&[0x01, 0x00]); // { key: 1, data: None }
+        bytes.extend_from_slice(&[0x02, 0x01, 0x00]); // { key: 2, data: Some Empty }
+                                                      // { key: 3, data: Some Pair("x", true) }
+        bytes.extend_from_slice(&[0x03, 0x01, 0x01, 0x01, b'x', 0x01]);
+        bytes
+    }
+
+    #[test]
+    fn navigates_without_full_decode() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let bytes = example_bytes();
+        let lazy = LazyValue::new(&bytes, &rule);
+
+        let entries = lazy.field("entries").unwrap();
+        assert_eq!(entries.len().unwrap(), 3);
+        let third = entries.get(2).unwrap();
+        assert_eq!(third.field("key").unwrap().decode_as::<i64>().unwrap(), 3);
+
+        let data = third.field("data").unwrap().option().unwrap().unwrap();
+        assert_eq!(data.variant().unwrap(), ("Pair", 1));
+        assert_eq!(data.get(0).unwrap().as_bytes().unwrap(), &[0x01, b'x']);
+        assert_eq!(data.get(1).unwrap().decode().unwrap(), Value::Bool(true));
+
+        assert!(entries
+            .get(0)
+            .unwrap()
+            .field("data")
+            .unwrap()
+            .option()
+            .unwrap()
+            .is_none());
+        assert!(entries.get(3).is_err());
+    }
+
+    #[test]
+    fn decodes_same_as_full_decode() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let bytes = example_bytes();
+        let lazy = LazyValue::new(&bytes, &rule);
+        assert_eq!(lazy.as_bytes().unwrap().len(), bytes.len());
+        assert_eq!(
+            lazy.field("entries")
+                .unwrap()
+                .get(1)
+                .unwrap()
+                .decode()
+                .unwrap(),
+            bin_prot_value!({ key: 2, data: Some(Empty#0) })
+        );
+    }
+
+    const VECTOR_RULE: &str = r#"
+    ["Reference", ["Resolved", {
+      "source_type_decl": "Pickles_types.Vector.Vector2.t",
+      "source_module_path": "Pickles_type.Vector.Vector2",
+      "ref_rule": ["Custom", [["Bool"]]]
+    }]]
+    "#;
+
+    #[test]
+    fn checks_vector_terminator() {
+        let rule: BinProtRule = serde_json::from_str(VECTOR_RULE).unwrap();
+        let bytes = [0x01, 0x00, 0x00];
+        let lazy = LazyValue::new(&bytes, &rule);
+        assert!(!lazy.get(1).unwrap().decode_as::<bool>().unwrap());
+        assert_eq!(lazy.as_bytes().unwrap().len(), 3);
+
+        // truncated before the terminator
+        let lazy = LazyValue::new(&bytes[..2], &rule);
+        assert!(matches!(lazy.get(0), Err(Error::Io(_))));
+
+        let bytes = [0x01, 0x00, 0x05];
+        let lazy = LazyValue::new(&bytes, &rule);
+        assert!(matches!(
+            lazy.get(0),
+            Err(Error::InvalidByte { byte: 0x05, .. })
+        ));
+    }
+
+    // the vector can only be read with the module path of the reference around the record
+    const NESTED_VECTOR_RULE: &str = r#"
+    ["Reference", ["Resolved", {
+      "source_type_decl": "Pickles_types.Vector.Vector2.t",
+      "source_module_path": "Pickles_type.Vector.Vector2",
+      "ref_rule": ["Record", [
+        { "field_name": "vector", "field_rule": ["Custom", [["Bool"]]] },
+        { "field_name": "last", "field_rule": ["Int"] }
+      ]]
+    }]]
+    "#;
+
+    #[test]
+    fn reads_vector_in_referenced_record() {
+        let rule: BinProtRule = serde_json::from_str(NESTED_VECTOR_RULE).unwrap();
+        let bytes = [0x01, 0x00, 0x00, 0x07];
+        let lazy = LazyValue::new(&bytes, &rule);
+        let vector = lazy.field("vector").unwrap();
+        assert_eq!(vector.len().unwrap(), 2);
+        assert!(vector.get(0).unwrap().decode_as::<bool>().unwrap());
+        assert_eq!(vector.as_bytes().unwrap().len(), 3);
+        assert_eq!(
+            vector.decode().unwrap(),
+            Value::List(vec![Value::Bool(true), Value::Bool(false)])
+        );
+        assert_eq!(lazy.field("last").unwrap().decode_as::<i64>().unwrap(), 7);
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `bin_prot_value!` macro for concisely building Values
+
+/// Construct a `value::Value` using a syntax similar to OCaml values and `serde_json::json!`.
+///
+/// - `()` is Unit
+/// - `{ field: value, ... }` is a Record with fields in the order given
+/// - `[a, b, ...]` is a List and `(a, b, ...)` is a Tuple
+/// - `None` and `Some(x)` are options
+/// - `Ctor#index` is a constant constructor and `Ctor#index(a, b, ...)` a constructor with
+///   arguments. The index is required since it is part of the Value and can't be inferred
+///   without the layout, so `Ctor(a, b, ...)` is not accepted
+/// - anything else is an expression converted with `Value::from`, e.g. `1`, `-5`, `"str"`, `true`
+///   or an existing Value
+///
+/// ```
+/// # use serde_bin_prot::bin_prot_value;
+/// # use serde_bin_prot::value::Value;
+/// let v = bin_prot_value!({ first: 0, second: Some(Two#1(false)), third: [(1, "a")] });
+/// assert_eq!(v["second"].inner(), Some(Value::sum("Two", 1, vec![Value::Bool(false)])));
+/// ```
+#[macro_export]
+macro_rules! bin_prot_value {
+    // Split a comma separated sequence of values into a Vec<Value>
+    (@items [$($done:expr,)*] [$($cur:tt)+] , $($rest:tt)*) => {
+        $crate::bin_prot_value!(@items [$($done,)* $crate::bin_prot_value!($($cur)+),] [] $($rest)*)
+    };
+    (@items [$($done:expr,)*] [$($cur:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::bin_prot_value!(@items [$($done,)*] [$($cur)* $next] $($rest)*)
+    };
+    (@items [$($done:expr,)*] []) => {
+        vec![$($done),*]
+    };
+    (@items [$($done:expr,)*] [$($cur:tt)+]) => {
+        vec![$($done,)* $crate::bin_prot_value!($($cur)+)]
+    };
+
+    // Split `name: value, ...` into a Vec<(String, Value)>
+    (@fields [$($done:expr,)*] $key:ident : $($rest:tt)*) => {
+        $crate::bin_prot_value!(@field [$($done,)*] $key [] $($rest)*)
+    };
+    (@fields [$($done:expr,)*]) => {
+        vec![$($done),*]
+    };
+    (@field [$($done:expr,)*] $key:ident [$($cur:tt)+] , $($rest:tt)*) => {
+        $crate::bin_prot_value!(
+            @fields [$($done,)* (stringify!($key).into(), $crate::bin_prot_value!($($cur)+)),]
+            $($rest)*
+        )
+    };
+    (@field [$($done:expr,)*] $key:ident [$($cur:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::bin_prot_value!(@field [$($done,)*] $key [$($cur)* $next] $($rest)*)
+    };
+    (@field [$($done:expr,)*] $key:ident [$($cur:tt)+]) => {
+        vec![$($done,)* (stringify!($key).into(), $crate::bin_prot_value!($($cur)+))]
+    };
+
+    (()) => {
+        $crate::value::Value::Unit
+    };
+    (None) => {
+        $crate::value::Value::Option(None)
+    };
+    (Some($($inner:tt)+)) => {
+        $crate::value::Value::Option(Some(Box::new($crate::bin_prot_value!($($inner)+))))
+    };
+    ({ $($fields:tt)* }) => {
+        $crate::value::Value::Record($crate::bin_prot_value!(@fields [] $($fields)*))
+    };
+    ([ $($items:tt)* ]) => {
+        $crate::value::Value::List($crate::bin_prot_value!(@items [] [] $($items)*))
+    };
+    (( $($items:tt)+ )) => {
+        $crate::value::Value::Tuple($crate::bin_prot_value!(@items [] [] $($items)+))
+    };
+    ($ctor:ident # $index:literal ( $($args:tt)* )) => {
+        $crate::value::Value::sum(
+            stringify!($ctor),
+            $index,
+            $crate::bin_prot_value!(@items [] [] $($args)*),
+        )
+    };
+    ($ctor:ident # $index:literal) => {
+        $crate::value::Value::sum(stringify!($ctor), $index, vec![])
+    };
+    ($other:expr) => {
+        $crate::value::Value::from($other)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::value::Value;
+
+    #[test]
+    fn builds_nested_values() {
+        let pk = Value::String(b"pk".to_vec());
+        assert_eq!(
+            bin_prot_value!({
+                first: -1,
+                second: Some(Two#1(false, [Empty#0, Key#2(pk.clone())])),
+                third: (),
+                fourth: None,
+                fifth: ("a", 'b', 1.5),
+            }),
+            Value::Record(vec![
+                ("first".into(), Value::Int(-1)),
+                (
+                    "second".into(),
+                    Value::Option(Some(Box::new(Value::Sum {
+                        name: "Two".into(),
+                        index: 1,
+                        value: Some(Box::new(Value::Tuple(vec![
+                            Value::Bool(false),
+                            Value::List(vec![
+                                Value::sum("Empty", 0, vec![]),
+                                Value::sum("Key", 2, vec![pk]),
+                            ]),
+                        ]))),
+                    }))),
+                ),
+                ("third".into(), Value::Unit),
+                ("fourth".into(), Value::Option(None)),
+                (
+                    "fifth".into(),
+                    Value::Tuple(vec![
+                        Value::String(b"a".to_vec()),
+                        Value::Char('b'),
+                        Value::Float(1.5)
+                    ])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_collections() {
+        assert_eq!(bin_prot_value!([]), Value::List(vec![]));
+        assert_eq!(bin_prot_value!({}), Value::Record(vec![]));
+    }
+}
@@ -6,22 +6,59 @@
 //! Since bin_prot is not a self describing format, deserializing to a loosely typed value required
 //! a supplimentary file that describes the layout of the binary (see layout/)
 
+use std::sync::Arc;
+
 use serde::Deserialize;
-use std::collections::HashMap;
 
+mod annotate;
+mod conform;
+mod convert;
+mod de;
+mod decode;
+mod diff;
+mod dump;
 mod enum_data;
 mod index;
+mod intern;
+mod json;
 pub mod layout;
+mod lazy;
+mod macros;
+mod plan;
+mod select;
+mod ser;
+mod sexp;
+mod skip;
+mod span;
 mod visitor;
+mod walk;
 
+pub use self::annotate::{from_reader_annotated, AnnotatedValue, TypeInfo};
+pub use self::conform::{Mismatch, MismatchKind};
+pub use self::de::from_value;
+pub(crate) use self::de::VariantDeserializer;
+pub use self::decode::decode_value;
+pub(crate) use self::decode::rule_name;
+pub use self::diff::{diff, render_diff, Difference, DifferenceKind};
+pub use self::dump::hex_dump;
 pub use self::index::Index;
+pub use self::json::JsonStyle;
+pub use self::lazy::LazyValue;
+pub use self::plan::DecodePlan;
+pub use self::select::decode_path;
+pub use self::ser::{to_value, Serializer};
+pub use self::skip::skip;
+pub(crate) use self::skip::CountingReader;
+pub use self::span::{spans, SizeReport, SizeStats, Span};
+pub use self::walk::{walk, walk_mut};
 pub use enum_data::EnumData;
 
 use visitor::ValueVisitor;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Default)]
 #[allow(dead_code)] // allow for now until full implementation
 pub enum Value {
+    #[default]
     Unit,
     Nat0(u32),
     Bool(bool),
@@ -30,22 +67,16 @@ pub enum Value {
     Int(i64),
     Float(f64),
     Option(Option<Box<Value>>),
-    Record(HashMap<String, Value>), // records/structs
+    Record(Vec<(Arc<str>, Value)>), // records/structs. Fields are kept in layout order
     Tuple(Vec<Value>),
     Sum {
-        name: String,
+        name: Arc<str>, // names are shared between values (see intern.rs)
         index: u8,
-        value: Box<Value>,
+        value: Option<Box<Value>>, // None for constant constructors, a Tuple for multiple args
     }, // sum types/enums
     List(Vec<Value>),
 }
 
-impl Default for Value {
-    fn default() -> Value {
-        Value::Unit
-    }
-}
-
 // Ensure the value visitor is always used when deserializing to a Value (see visitor.rs)
 //
 // This will always request `deserialize_any` be called since the Value implementation
@@ -61,7 +92,26 @@ impl<'de> Deserialize<'de> for Value {
     }
 }
 
+/// The payload of a Sum with the given constructor arguments (see `Value::sum`)
+pub(crate) fn sum_payload(mut args: Vec<Value>) -> Option<Box<Value>> {
+    match args.len() {
+        0 => None,
+        1 => args.pop().map(Box::new),
+        _ => Some(Box::new(Value::Tuple(args))),
+    }
+}
+
 impl Value {
+    /// Builds a Sum from its constructor arguments. The payload is None for a
+    /// constant constructor, the argument itself for one argument and a Tuple otherwise
+    pub fn sum(name: &str, index: u8, args: Vec<Value>) -> Self {
+        Value::Sum {
+            name: Arc::from(name),
+            index,
+            value: sum_payload(args),
+        }
+    }
+
     /// Inner reveals an Option variant as a Rust Option type
     /// Calling inner on a non-option variant results in a panic
     pub fn inner(&self) -> Option<Self> {
@@ -71,4 +121,109 @@ impl Value {
             panic!("Called inner on a non-option variant {:?}", self)
         }
     }
+
+    /// Index into a record, list or tuple. Returns None if the index does not exist
+    /// rather than panicking like the `[]` operator
+    pub fn get<I: Index>(&self, index: I) -> Option<&Value> {
+        index.index_into(self)
+    }
+
+    /// Mutably index into a record, list or tuple. Returns None if the index does not exist
+    pub fn get_mut<I: Index>(&mut self, index: I) -> Option<&mut Value> {
+        index.index_into_mut(self)
+    }
+
+    /// Returns the integer if this is an Int or Nat0 variant
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Int(i) => Some(i),
+            Value::Nat0(n) => Some(n.into()),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Returns the raw bytes of a String variant
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::String(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Returns the String variant as a str if it contains valid utf-8
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|b| std::str::from_utf8(b).ok())
+    }
+
+    /// Returns the fields of a Record in layout order
+    pub fn as_record(&self) -> Option<&[(Arc<str>, Value)]> {
+        match self {
+            Value::Record(fields) => Some(fields),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements of a List or Tuple
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(items) | Value::Tuple(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Returns the constructor name, index and payload of a Sum.
+    /// The payload is None for a constant constructor
+    pub fn as_sum(&self) -> Option<(&str, u8, Option<&Value>)> {
+        match self {
+            Value::Sum { name, index, value } => Some((name, *index, value.as_deref())),
+            _ => None,
+        }
+    }
+
+    /// Looks up a value by a JSON Pointer style path e.g. `/protocol_state/body/0`.
+    /// Each segment is either a record field name, a list/tuple index or the name of a
+    /// constructor, which selects the payload of a Sum as in the paths given by `diff` and `walk`.
+    /// Options and sums that do not match a segment directly are stepped through transparently
+    /// so the path only needs to name the fields and indices.
+    pub fn pointer(&self, pointer: &str) -> Option<&Value> {
+        if pointer.is_empty() {
+            return Some(self);
+        }
+        if !pointer.starts_with('/') {
+            return None;
+        }
+        pointer
+            .split('/')
+            .skip(1)
+            .map(|x| x.replace("~1", "/").replace("~0", "~"))
+            .try_fold(self, |target, token| {
+                let mut target = target;
+                loop {
+                    let next = match token.parse::<usize>() {
+                        Ok(i) => target.get(i),
+                        Err(_) => target.get(&token),
+                    };
+                    if next.is_some() {
+                        return next;
+                    }
+                    target = match target {
+                        Value::Option(Some(inner)) => inner,
+                        Value::Sum { name, value, .. } if **name == *token => {
+                            return value.as_deref()
+                        }
+                        Value::Sum {
+                            value: Some(value), ..
+                        } => value,
+                        _ => return None,
+                    };
+                }
+            })
+    }
 }
@@ -0,0 +1,316 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Compiled decode plans.
+//!
+//! Decoding with `Deserializer::from_reader_with_layout` walks the layout rule tree for every
+//! message, following references and keeping track of the path for error reporting.
+//! A `DecodePlan` instead compiles the layout once into a flat vector of ops that refer to
+//! each other by index. Sums become jump tables indexed by the variant byte and references are
+//! resolved at compile time. Decoding then only reads the plan, so a single plan can be reused
+//! for any number of messages and shared between threads.
+
+use std::io::Read;
+use std::sync::Arc;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use super::{sum_payload, Value};
+use crate::error::{Error, Result};
+use crate::value::layout::{
+    capacity_for, read_vector_terminator, select_summand, BinProtRule, ModulePath, RuleRef, Walker,
+};
+use crate::ReadBinProtExt;
+
+/// A layout compiled for decoding into Values
+#[derive(Clone, Debug)]
+pub struct DecodePlan {
+    ops: Vec<Op>,
+    root: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    Leaf(Leaf),
+    Option(usize),
+    Record(Vec<(Arc<str>, usize)>),
+    Tuple(Vec<usize>),
+    List(usize),
+    Sum(Vec<Branch>), // jump table, indexed by the variant byte
+    // custom Pickles vectors with a fixed length and a zero byte terminator
+    FixedVector(usize, usize),
+}
+
+/// A rule with no children, which is read with a single reader call.
+/// Also used for the leaves of a hex dump so they show what a plan would decode
+#[derive(Clone, Copy, Debug)]
+pub(super) enum Leaf {
+    Unit,
+    Bool,
+    String,
+    Char,
+    Int,
+    Nat0,
+    Float,
+}
+
+impl Leaf {
+    /// The leaf a rule (with any references already followed) is read as, if any
+    pub(super) fn of(rule: &BinProtRule) -> Option<Self> {
+        Some(match rule {
+            BinProtRule::Unit => Leaf::Unit,
+            BinProtRule::Bool => Leaf::Bool,
+            BinProtRule::String => Leaf::String,
+            BinProtRule::Char => Leaf::Char,
+            BinProtRule::Int | BinProtRule::Int32 | BinProtRule::Int64 | BinProtRule::NativeInt => {
+                Leaf::Int
+            }
+            BinProtRule::Nat0 => Leaf::Nat0,
+            BinProtRule::Float => Leaf::Float,
+            _ => return None,
+        })
+    }
+
+    pub(super) fn read<R: Read>(self, rdr: &mut R) -> Result<Value> {
+        Ok(match self {
+            Leaf::Unit => {
+                rdr.bin_read_unit()?;
+                Value::Unit
+            }
+            Leaf::Bool => Value::Bool(rdr.bin_read_bool()?),
+            Leaf::String => Value::String(rdr.bin_read_bytes()?),
+            Leaf::Char => Value::Char(rdr.bin_read_char()?),
+            Leaf::Int => Value::Int(rdr.bin_read_integer()?),
+            Leaf::Nat0 => Value::Nat0(rdr.bin_read_nat0()?),
+            Leaf::Float => Value::Float(rdr.read_f64::<LittleEndian>()?),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Branch {
+    name: Arc<str>,
+    args: Vec<usize>,
+}
+
+impl DecodePlan {
+    /// Compile a layout rule into a plan.
+    /// Fails if the rule contains a type with no decoding strategy e.g. a polymorphic variant
+    pub fn compile(rule: &BinProtRule) -> Result<Self> {
+        let mut compiler = Compiler {
+            ops: Vec::new(),
+            module_path: ModulePath::default(),
+        };
+        let root = compiler.compile(rule)?;
+        Ok(Self {
+            ops: compiler.ops,
+            root,
+        })
+    }
+
+    /// Decode a single value from the reader.
+    /// Only the bytes of the value are read so consecutive values can be read from one stream
+    pub fn decode<R: Read>(&self, mut rdr: R) -> Result<Value> {
+        self.exec(self.root, &mut rdr)
+    }
+
+    fn exec<R: Read>(&self, op: usize, rdr: &mut R) -> Result<Value> {
+        Ok(match &self.ops[op] {
+            Op::Leaf(leaf) => leaf.read(rdr)?,
+            Op::Option(inner) => match rdr.bin_read_bool()? {
+                false => Value::Option(None),
+                true => Value::Option(Some(Box::new(self.exec(*inner, rdr)?))),
+            },
+            Op::Record(fields) => Value::Record(
+                fields
+                    .iter()
+                    .map(|(name, field)| Ok((name.clone(), self.exec(*field, rdr)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            Op::Tuple(items) => Value::Tuple(
+                items
+                    .iter()
+                    .map(|item| self.exec(*item, rdr))
+                    .collect::<Result<_>>()?,
+            ),
+            Op::List(element) => {
+                let len = rdr.bin_read_nat0::<u64>()? as usize;
+                Value::List(self.exec_repeated(*element, len, rdr)?)
+            }
+            Op::Sum(branches) => {
+                let index = rdr.bin_read_variant_index()?;
+                let branch = select_summand(branches, index)?;
+                let args = branch
+                    .args
+                    .iter()
+                    .map(|arg| self.exec(*arg, rdr))
+                    .collect::<Result<Vec<_>>>()?;
+                Value::Sum {
+                    name: branch.name.clone(),
+                    index,
+                    value: sum_payload(args),
+                }
+            }
+            Op::FixedVector(len, element) => {
+                let items = self.exec_repeated(*element, *len, rdr)?;
+                read_vector_terminator(rdr)?;
+                Value::List(items)
+            }
+        })
+    }
+
+    fn exec_repeated<R: Read>(&self, op: usize, len: usize, rdr: &mut R) -> Result<Vec<Value>> {
+        let mut items = Vec::with_capacity(capacity_for(len));
+        for _ in 0..len {
+            items.push(self.exec(op, rdr)?);
+        }
+        Ok(items)
+    }
+}
+
+struct Compiler {
+    ops: Vec<Op>,
+    module_path: ModulePath,
+}
+
+impl Walker for Compiler {
+    fn module_path(&mut self) -> &mut ModulePath {
+        &mut self.module_path
+    }
+}
+
+impl Compiler {
+    // compiles the rule and its children, returning the index of its op
+    fn compile(&mut self, rule: &BinProtRule) -> Result<usize> {
+        if let Some(leaf) = Leaf::of(rule) {
+            self.ops.push(Op::Leaf(leaf));
+            return Ok(self.ops.len() - 1);
+        }
+        let op = match rule {
+            BinProtRule::Option(r) => Op::Option(self.compile(r)?),
+            BinProtRule::Record(fields) => Op::Record(
+                fields
+                    .iter()
+                    .map(|f| Ok((f.field_name.clone(), self.compile(&f.field_rule)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            BinProtRule::Tuple(rules) => Op::Tuple(self.compile_all(rules)?),
+            BinProtRule::List(r) => Op::List(self.compile(r)?),
+            BinProtRule::Sum(summands) => Op::Sum(
+                summands
+                    .iter()
+                    .map(|s| {
+                        Ok(Branch {
+                            name: s.ctor_name.clone(),
+                            args: self.compile_all(&s.ctor_args)?,
+                        })
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            BinProtRule::Reference(RuleRef::Resolved(payload)) => {
+                return self.within_reference(payload, |c, r| c.compile(r));
+            }
+            BinProtRule::Custom(rules) => {
+                let (len, element) = self.module_path.custom_vector(rules)?;
+                Op::FixedVector(len, self.compile(element)?)
+            }
+            r => {
+                return Err(Error::Custom {
+                    message: format!("No strategy to compile {:?}", r),
+                })
+            }
+        };
+        self.ops.push(op);
+        Ok(self.ops.len() - 1)
+    }
+
+    fn compile_all(&mut self, rules: &[BinProtRule]) -> Result<Vec<usize>> {
+        rules.iter().map(|r| self.compile(r)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin_prot_value;
+    use crate::Deserializer;
+    use serde::Deserialize;
+
+    const RULE: &str = r#"
+    ["List", ["Record", [
+      { "field_name": "first", "field_rule": ["Option", ["Tuple", [["Int"], ["Bool"]]]] },
+      { "field_name": "second", "field_rule": ["Sum", [
+        { "ctor_name": "One", "index": 0, "ctor_args": [] },
+        { "ctor_name": "Two", "index": 1, "ctor_args": [["String"], ["Float"]] }
+      ]] }
+    ]]]
+    "#;
+
+    fn example_bytes() -> Vec<u8> {
+        let mut bytes = vec![0x02]; // 2 elements
+        bytes.extend_from_slice(&[0x01, 0x05, 0x01, 0x00]); // Some((5, true)), One
+        bytes.extend_from_slice(&[0x00, 0x01, 0x01, b'a']); // None, Two("a", ..
+        bytes.extend_from_slice(&1.5_f64.to_le_bytes()); // .. 1.5)
+        bytes
+    }
+
+    #[test]
+    fn decodes_list_of_records() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let plan = DecodePlan::compile(&rule).unwrap();
+        assert_eq!(
+            plan.decode(example_bytes().as_slice()).unwrap(),
+            bin_prot_value!([
+                { first: Some((5, true)), second: One#0 },
+                { first: None, second: Two#1("a", 1.5) }
+            ])
+        );
+    }
+
+    #[test]
+    fn decodes_same_as_deserializer() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let plan = DecodePlan::compile(&rule).unwrap();
+        let bytes = example_bytes();
+        let mut de = Deserializer::from_reader_with_layout(bytes.as_slice(), rule);
+        let loose: Value = Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(plan.decode(bytes.as_slice()).unwrap(), loose);
+    }
+
+    #[test]
+    fn records_share_field_names() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let plan = DecodePlan::compile(&rule).unwrap();
+        let names = |v: &Value| match v {
+            Value::Record(fields) => fields[0].0.clone(),
+            v => panic!("unexpected value {:?}", v),
+        };
+        match plan.decode(example_bytes().as_slice()).unwrap() {
+            Value::List(items) => assert!(Arc::ptr_eq(&names(&items[0]), &names(&items[1]))),
+            v => panic!("unexpected value {:?}", v),
+        }
+    }
+
+    #[test]
+    fn plan_is_reusable_on_a_stream() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let plan = DecodePlan::compile(&rule).unwrap();
+        let mut bytes = example_bytes();
+        bytes.extend(example_bytes());
+        let mut rdr = bytes.as_slice();
+        let first = plan.decode(&mut rdr).unwrap();
+        let second = plan.decode(&mut rdr).unwrap();
+        assert_eq!(first, second);
+        assert!(rdr.is_empty());
+    }
+
+    #[test]
+    fn invalid_variant_byte_errors() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let plan = DecodePlan::compile(&rule).unwrap();
+        assert!(matches!(
+            plan.decode([0x01, 0x00, 0x02].as_ref()),
+            Err(Error::InvalidByte { byte: 0x02, .. })
+        ));
+    }
+}
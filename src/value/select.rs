@@ -0,0 +1,245 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decoding a single field out of a larger message.
+//!
+//! `decode_path` follows a dotted path of record field names, tuple and list indices and
+//! constructor names through the layout. Everything encoded before the requested field is
+//! skipped over (see `skip`) and only the field itself is decoded. Nothing after the field
+//! is decoded.
+
+use std::io::Read;
+
+use serde::de::DeserializeOwned;
+
+use super::skip::Skipper;
+use crate::error::{Error, Result};
+use crate::value::layout::{select_summand, BinProtRule, RuleRef, Walker};
+use crate::{Deserializer, ReadBinProtExt};
+
+/// Decode only the value at `path` e.g. "protocol_state.body.consensus_state".
+/// Segments are record field names, tuple, list or vector indices and constructor names, and
+/// an `Option` along the way is passed through if it holds a value. As in `Value::sum` the
+/// arguments of a constructor with more than one are a tuple e.g. "body.Payment.0".
+/// The result can be a `Value` or any type implementing `Deserialize`.
+/// An empty path decodes the whole message.
+pub fn decode_path<R: Read, T: DeserializeOwned>(
+    rdr: R,
+    rule: &BinProtRule,
+    path: &str,
+) -> Result<T> {
+    let mut skipper = Skipper::new(rdr);
+    let segments: Vec<&str> = path.split('.').filter(|s| !s.is_empty()).collect();
+    decode_at(&mut skipper, rule, &segments, path)
+}
+
+// skips the siblings before the first segment and continues with its child. The node at the
+// end of the path is decoded with the module path of its enclosing reference
+fn decode_at<R: Read, T: DeserializeOwned>(
+    skipper: &mut Skipper<R>,
+    rule: &BinProtRule,
+    segments: &[&str],
+    path: &str,
+) -> Result<T> {
+    let (segment, rest) = match segments.split_first() {
+        Some((segment, rest)) => (*segment, rest),
+        None => {
+            let module_path = skipper.module_path.clone();
+            let mut de = Deserializer::from_reader_with_layout(&mut skipper.rdr, rule.clone())
+                .with_module_path(module_path);
+            return T::deserialize(&mut de);
+        }
+    };
+    match rule {
+        BinProtRule::Reference(RuleRef::Resolved(payload)) => {
+            skipper.within_reference(payload, |s, r| decode_at(s, r, segments, path))
+        }
+        BinProtRule::Option(r) => {
+            if skipper.rdr.bin_read_bool()? {
+                decode_at(skipper, r, segments, path)
+            } else {
+                Err(Error::Custom {
+                    message: format!("No value at {}, {} is None", path, segment),
+                })
+            }
+        }
+        BinProtRule::Record(fields) => {
+            let position = fields
+                .iter()
+                .position(|f| *f.field_name == *segment)
+                .ok_or_else(|| not_found(segment, path))?;
+            for field in &fields[..position] {
+                skipper.skip(&field.field_rule)?;
+            }
+            decode_at(skipper, &fields[position].field_rule, rest, path)
+        }
+        BinProtRule::Sum(summands) => {
+            let summand = select_summand(summands, skipper.rdr.bin_read_variant_index()?)?;
+            if *summand.ctor_name != *segment {
+                return Err(Error::Custom {
+                    message: format!(
+                        "No value at {}, the constructor is {} not {}",
+                        path, summand.ctor_name, segment
+                    ),
+                });
+            }
+            match &summand.ctor_args[..] {
+                [] => decode_at(skipper, &BinProtRule::Unit, rest, path),
+                [arg] => decode_at(skipper, arg, rest, path),
+                _ => {
+                    let args = BinProtRule::Tuple(summand.ctor_args.clone());
+                    decode_at(skipper, &args, rest, path)
+                }
+            }
+        }
+        BinProtRule::List(r) => {
+            let len = skipper.rdr.bin_read_nat0::<u64>()?;
+            let position = index(segment, len as usize, "list", path)?;
+            for _ in 0..position {
+                skipper.skip(r)?;
+            }
+            decode_at(skipper, r, rest, path)
+        }
+        BinProtRule::Custom(rules) => {
+            let (len, element) = skipper.module_path.custom_vector(rules)?;
+            let position = index(segment, len, "vector", path)?;
+            for _ in 0..position {
+                skipper.skip(element)?;
+            }
+            decode_at(skipper, element, rest, path)
+        }
+        BinProtRule::Tuple(rules) => {
+            let position = segment
+                .parse::<usize>()
+                .ok()
+                .filter(|i| *i < rules.len())
+                .ok_or_else(|| not_found(segment, path))?;
+            for r in &rules[..position] {
+                skipper.skip(r)?;
+            }
+            decode_at(skipper, &rules[position], rest, path)
+        }
+        _ => Err(not_found(segment, path)),
+    }
+}
+
+// the position of an element of a list or vector of length `len`
+fn index(segment: &str, len: usize, kind: &str, path: &str) -> Result<usize> {
+    segment
+        .parse::<usize>()
+        .ok()
+        .filter(|i| *i < len)
+        .ok_or_else(|| Error::Custom {
+            message: format!(
+                "No element {} in {} of length {} for path {}",
+                segment, kind, len, path
+            ),
+        })
+}
+
+fn not_found(segment: &str, path: &str) -> Error {
+    Error::Custom {
+        message: format!("No field {} in layout for path {}", segment, path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+
+    const RULE: &str = r#"
+    ["Record", [
+      { "field_name": "header", "field_rule": ["List", ["String"]] },
+      { "field_name": "body", "field_rule": ["Option", ["Record", [
+        { "field_name": "fee", "field_rule": ["Int"] },
+        { "field_name": "state", "field_rule": ["Tuple", [["Bool"], ["Record", [
+          { "field_name": "length", "field_rule": ["Int"] }
+        ]]]] }
+      ]]] },
+      { "field_name": "trailer", "field_rule": ["String"] }
+    ]]
+    "#;
+
+    // header: ["ab"], body: Some { fee: 3, state: (true, { length: 7 }) } then garbage
+    const BYTES: &[u8] = &[0x01, 0x02, b'a', b'b', 0x01, 0x03, 0x01, 0x07, 0xff, 0xff];
+
+    #[test]
+    fn decodes_nested_field_as_value() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let state: Value = decode_path(BYTES, &rule, "body.state").unwrap();
+        assert_eq!(state[1]["length"], Value::Int(7));
+    }
+
+    #[test]
+    fn decodes_nested_field_as_typed() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let length: i64 = decode_path(BYTES, &rule, "body.state.1.length").unwrap();
+        assert_eq!(length, 7);
+    }
+
+    const SUM_RULE: &str = r#"
+    ["List", ["Sum", [
+      { "ctor_name": "One", "index": 0, "ctor_args": [] },
+      { "ctor_name": "Two", "index": 1, "ctor_args": [["Bool"], ["Int"]] }
+    ]]]
+    "#;
+
+    #[test]
+    fn follows_list_indices_and_constructors() {
+        let rule: BinProtRule = serde_json::from_str(SUM_RULE).unwrap();
+        let bytes: &[u8] = &[0x02, 0x00, 0x01, 0x01, 0x05]; // [One, Two(true, 5)]
+        let second: i64 = decode_path(bytes, &rule, "1.Two.1").unwrap();
+        assert_eq!(second, 5);
+        let args: Value = decode_path(bytes, &rule, "1.Two").unwrap();
+        assert_eq!(args, Value::Tuple(vec![Value::Bool(true), Value::Int(5)]));
+        assert!(decode_path::<_, Value>(bytes, &rule, "0.Two").is_err());
+        assert!(decode_path::<_, Value>(bytes, &rule, "2").is_err());
+    }
+
+    // the vector can only be skipped once the module path is restored after the inner reference
+    const VECTOR_RULE: &str = r#"
+    ["Reference", ["Resolved", {
+      "source_type_decl": "Pickles_types.Vector.Vector2.t",
+      "source_module_path": "Pickles_type.Vector.Vector2",
+      "ref_rule": ["Record", [
+        { "field_name": "flag", "field_rule": ["Reference", ["Resolved", {
+          "source_type_decl": "Flag.t",
+          "source_module_path": "Flag",
+          "ref_rule": ["Bool"]
+        }]] },
+        { "field_name": "vector", "field_rule": ["Custom", [["Bool"]]] },
+        { "field_name": "last", "field_rule": ["Int"] }
+      ]]
+    }]]
+    "#;
+
+    #[test]
+    fn restores_module_path_after_reference() {
+        let rule: BinProtRule = serde_json::from_str(VECTOR_RULE).unwrap();
+        let bytes: &[u8] = &[0x01, 0x01, 0x00, 0x00, 0x07];
+        let last: i64 = decode_path(bytes, &rule, "last").unwrap();
+        assert_eq!(last, 7);
+    }
+
+    #[test]
+    fn unknown_field_errors() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        assert!(decode_path::<_, Value>(BYTES, &rule, "body.missing").is_err());
+        assert!(decode_path::<_, Value>(BYTES, &rule, "body.fee.length").is_err());
+    }
+
+    #[test]
+    fn follows_vector_indices() {
+        let rule: BinProtRule = serde_json::from_str(VECTOR_RULE).unwrap();
+        let bytes: &[u8] = &[0x01, 0x00, 0x01, 0x00, 0x07];
+        let second: bool = decode_path(bytes, &rule, "vector.1").unwrap();
+        assert!(second);
+        let vector: Value = decode_path(bytes, &rule, "vector").unwrap();
+        assert_eq!(
+            vector,
+            Value::List(vec![Value::Bool(false), Value::Bool(true)])
+        );
+        assert!(decode_path::<_, Value>(bytes, &rule, "vector.2").is_err());
+    }
+}
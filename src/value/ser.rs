@@ -0,0 +1,574 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Serialization of Values.
+//!
+//! Value implements Serialize so it can be written out as bin_prot directly without a layout.
+//! The structure of a Value (ordered records, sum indices, nat0 and list lengths) carries
+//! everything that is required by the encoding.
+//!
+//! This module also implements a serde::Serializer that builds a Value from any Rust type
+//! implementing Serialize. Structs become records with their field names in declaration order,
+//! enums become sums with their variant name and index. Maps become a list of (key, value)
+//! tuples which mirrors how bin_prot lays out a hashtable.
+
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use serde::ser::{self, Serialize, SerializeSeq, SerializeTuple, SerializeTupleVariant as _};
+
+use super::Value;
+use crate::error::{Error, Result};
+use crate::integers::nat0;
+
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        match self {
+            Value::Unit => serializer.serialize_unit(),
+            Value::Nat0(n) => nat0::serialize(n, serializer),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            // Strings are a nat0 length followed by the raw bytes. Bytes that are not valid utf-8
+            // are written as a sequence of u8 which produces the same encoding
+            Value::String(bytes) => match std::str::from_utf8(bytes) {
+                Ok(s) => serializer.serialize_str(s),
+                Err(_) => {
+                    let mut seq = serializer.serialize_seq(Some(bytes.len()))?;
+                    for b in bytes {
+                        seq.serialize_element(b)?;
+                    }
+                    seq.end()
+                }
+            },
+            Value::Char(c) => serializer.serialize_char(*c),
+            Value::Int(i) => serializer.serialize_i64(*i),
+            Value::Float(f) => serializer.serialize_f64(*f),
+            Value::Option(None) => serializer.serialize_none(),
+            Value::Option(Some(v)) => serializer.serialize_some(v),
+            // Field names are not part of the encoding so records are written like tuples
+            Value::Record(fields) => {
+                let mut tup = serializer.serialize_tuple(fields.len())?;
+                for (_, v) in fields {
+                    tup.serialize_element(v)?;
+                }
+                tup.end()
+            }
+            Value::Tuple(items) => {
+                let mut tup = serializer.serialize_tuple(items.len())?;
+                for v in items {
+                    tup.serialize_element(v)?;
+                }
+                tup.end()
+            }
+            Value::List(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for v in items {
+                    seq.serialize_element(v)?;
+                }
+                seq.end()
+            }
+            // The payload shape mirrors how it was decoded (see visitor.rs). Only the variant index
+            // is written so the variant name is not required to be static
+            Value::Sum { index, value, .. } => match value.as_deref() {
+                None => serializer.serialize_unit_variant("", (*index).into(), ""),
+                Some(Value::Tuple(args)) => {
+                    let mut var =
+                        serializer.serialize_tuple_variant("", (*index).into(), "", args.len())?;
+                    for v in args {
+                        var.serialize_field(v)?;
+                    }
+                    var.end()
+                }
+                Some(v) => serializer.serialize_newtype_variant("", (*index).into(), "", v),
+            },
+        }
+    }
+}
+
+/// Convert a `T` into a loosely typed `Value`
+pub fn to_value<T>(value: &T) -> Result<Value>
+where
+    T: ?Sized + Serialize,
+{
+    value.serialize(Serializer)
+}
+
+fn variant_index(index: u32) -> Result<u8> {
+    u8::try_from(index).map_err(|_| Error::Custom {
+        message: format!("Variant index {} does not fit in a u8", index),
+    })
+}
+
+fn sum(variant: &'static str, index: u32, value: Option<Value>) -> Result<Value> {
+    Ok(Value::Sum {
+        name: Arc::from(variant),
+        index: variant_index(index)?,
+        value: value.map(Box::new),
+    })
+}
+
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Value;
+    type Error = Error;
+
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeTupleVariant;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeStruct;
+    type SerializeStructVariant = SerializeStructVariant;
+
+    fn serialize_bool(self, v: bool) -> Result<Value> {
+        Ok(Value::Bool(v))
+    }
+
+    // All integers are mapped to Int in the same way the bin_prot serializer
+    // maps them to the Integer encoding
+    fn serialize_i8(self, v: i8) -> Result<Value> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value> {
+        Ok(Value::Int(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value> {
+        i64::try_from(v)
+            .map(Value::Int)
+            .map_err(|_| Error::DestinationIntegerOverflow)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value> {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value> {
+        Ok(Value::Char(v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value> {
+        Ok(Value::String(v.as_bytes().to_vec()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value> {
+        Ok(Value::String(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Value> {
+        Ok(Value::Option(None))
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Value>
+    where
+        T: ?Sized + Serialize,
+    {
+        Ok(Value::Option(Some(Box::new(value.serialize(self)?))))
+    }
+
+    fn serialize_unit(self) -> Result<Value> {
+        Ok(Value::Unit)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value> {
+        sum(variant, variant_index, None)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<Value>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value>
+    where
+        T: ?Sized + Serialize,
+    {
+        sum(variant, variant_index, Some(value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Ok(SerializeVec {
+            vec: Vec::with_capacity(len.unwrap_or(0)),
+            is_list: true,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+        Ok(SerializeVec {
+            vec: Vec::with_capacity(len),
+            is_list: false,
+        })
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        self.serialize_tuple(len)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Ok(SerializeTupleVariant {
+            name: variant,
+            index: variant_index,
+            vec: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
+        Ok(SerializeMap {
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        Ok(SerializeStruct {
+            fields: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Ok(SerializeStructVariant {
+            name: variant,
+            index: variant_index,
+            fields: Vec::with_capacity(len),
+        })
+    }
+}
+
+// Sequences become a List, tuples and tuple structs become a Tuple
+pub struct SerializeVec {
+    vec: Vec<Value>,
+    is_list: bool,
+}
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.vec.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        if self.is_list {
+            Ok(Value::List(self.vec))
+        } else {
+            Ok(Value::Tuple(self.vec))
+        }
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+pub struct SerializeTupleVariant {
+    name: &'static str,
+    index: u32,
+    vec: Vec<Value>,
+}
+
+impl ser::SerializeTupleVariant for SerializeTupleVariant {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.vec.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        sum(self.name, self.index, Some(Value::Tuple(self.vec)))
+    }
+}
+
+// Maps are written as a list of (key, value) pairs
+pub struct SerializeMap {
+    entries: Vec<Value>,
+    next_key: Option<Value>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.next_key = Some(to_value(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let key = self.next_key.take().ok_or_else(|| Error::Custom {
+            message: "serialize_value called before serialize_key".to_string(),
+        })?;
+        self.entries.push(Value::Tuple(vec![key, to_value(value)?]));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::List(self.entries))
+    }
+}
+
+pub struct SerializeStruct {
+    fields: Vec<(Arc<str>, Value)>,
+}
+
+impl ser::SerializeStruct for SerializeStruct {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.fields.push((Arc::from(key), to_value(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::Record(self.fields))
+    }
+}
+
+pub struct SerializeStructVariant {
+    name: &'static str,
+    index: u32,
+    fields: Vec<(Arc<str>, Value)>,
+}
+
+impl ser::SerializeStructVariant for SerializeStructVariant {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.fields.push((Arc::from(key), to_value(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        sum(self.name, self.index, Some(Value::Record(self.fields)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::from_value;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Block {
+        height: u32,
+        body: Option<Body>,
+        tags: Vec<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Body {
+        Empty,
+        Pair(i64, bool),
+        Named { x: i8 },
+    }
+
+    #[test]
+    fn struct_to_ordered_record() {
+        let block = Block {
+            height: 3,
+            body: Some(Body::Pair(-1, true)),
+            tags: vec!["a".to_string()],
+        };
+        assert_eq!(
+            to_value(&block).unwrap(),
+            Value::Record(vec![
+                ("height".into(), Value::Int(3)),
+                (
+                    "body".into(),
+                    Value::Option(Some(Box::new(Value::Sum {
+                        name: "Pair".into(),
+                        index: 1,
+                        value: Some(Box::new(Value::Tuple(vec![
+                            Value::Int(-1),
+                            Value::Bool(true)
+                        ]))),
+                    })))
+                ),
+                (
+                    "tags".into(),
+                    Value::List(vec![Value::String(b"a".to_vec())])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn enum_variants_to_sums() {
+        assert_eq!(
+            to_value(&Body::Empty).unwrap(),
+            Value::Sum {
+                name: "Empty".into(),
+                index: 0,
+                value: None,
+            }
+        );
+        assert_eq!(
+            to_value(&Body::Named { x: 2 }).unwrap(),
+            Value::Sum {
+                name: "Named".into(),
+                index: 2,
+                value: Some(Box::new(Value::Record(vec![("x".into(), Value::Int(2))]))),
+            }
+        );
+    }
+
+    #[test]
+    fn maps_to_list_of_pairs() {
+        let mut map = BTreeMap::new();
+        map.insert(1, true);
+        assert_eq!(
+            to_value(&map).unwrap(),
+            Value::List(vec![Value::Tuple(vec![Value::Int(1), Value::Bool(true)])])
+        );
+    }
+
+    #[test]
+    fn value_serializes_to_bin_prot() {
+        let val = Value::Record(vec![
+            ("len".into(), Value::Nat0(0x8000)),
+            ("raw".into(), Value::String(vec![0xff, 0x00])),
+            (
+                "sum".into(),
+                Value::Sum {
+                    name: "Pair".into(),
+                    index: 1,
+                    value: Some(Box::new(Value::Tuple(vec![
+                        Value::Int(-1),
+                        Value::Bool(true),
+                    ]))),
+                },
+            ),
+        ]);
+        let mut output = Vec::new();
+        crate::to_writer(&mut output, &val).unwrap();
+        assert_eq!(
+            output,
+            vec![0xfe, 0x00, 0x80, 0x02, 0xff, 0x00, 0x01, 0xff, 0xff, 0x01]
+        );
+    }
+
+    #[test]
+    fn roundtrip_through_value() {
+        let block = Block {
+            height: 10,
+            body: Some(Body::Named { x: -4 }),
+            tags: vec![],
+        };
+        let val = to_value(&block).unwrap();
+        assert_eq!(from_value::<Block>(val).unwrap(), block);
+    }
+}
@@ -0,0 +1,548 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Conversion between Values and s-expressions using the conventions of `ppx_sexp_conv`.
+//!
+//! Records are `((field value) ...)`, constructors are `Ctor` or `(Ctor args...)`,
+//! options are `()` or `(x)` and lists and tuples are `(a b ...)`.
+//! Reading a Value back from an s-expression requires the layout since the
+//! s-expression does not distinguish atoms of different types.
+
+use std::convert::TryFrom;
+
+use super::{sum_payload, Value};
+use crate::error::{Error, Result};
+use crate::sexp::Sexp;
+use crate::value::layout::{summand_index, BinProtRule, ModulePath, RuleRef, Summand, Walker};
+
+impl Value {
+    /// Convert this value to an s-expression as `sexp_of_t` would in OCaml.
+    /// Without the layout a constructor with a single unit argument can't be told from a
+    /// constant constructor, or a single tuple argument from several arguments
+    /// (see `to_sexp_with_layout`)
+    pub fn to_sexp(&self) -> Sexp {
+        sexp_of(self, None)
+    }
+
+    /// Convert this value to an s-expression using the layout rule to print constructors as
+    /// they are declared e.g. `(Ctor ())` for a single unit argument.
+    /// Any part of the value that does not match the rule is converted as by `to_sexp`
+    pub fn to_sexp_with_layout(&self, rule: &BinProtRule) -> Sexp {
+        sexp_of(self, Some(rule))
+    }
+
+    /// Read a value from an s-expression as `t_of_sexp` would in OCaml.
+    /// The layout rule determines how each atom is interpreted. Record fields that are not in
+    /// the layout are rejected
+    pub fn from_sexp(sexp: &Sexp, rule: &BinProtRule) -> Result<Value> {
+        SexpReader {
+            path: Vec::new(),
+            module_path: ModulePath::default(),
+        }
+        .read(sexp, rule)
+    }
+}
+
+fn sexp_of(value: &Value, rule: Option<&BinProtRule>) -> Sexp {
+    if let Some(BinProtRule::Reference(RuleRef::Resolved(payload))) = rule {
+        return sexp_of(value, Some(&payload.ref_rule));
+    }
+    match value {
+        Value::Unit => Sexp::List(vec![]),
+        Value::Nat0(n) => Sexp::atom(n),
+        Value::Bool(b) => Sexp::atom(b),
+        Value::String(bytes) => Sexp::Atom(bytes.clone()),
+        Value::Char(c) => match u8::try_from(*c) {
+            Ok(b) => Sexp::Atom(vec![b]),
+            Err(_) => Sexp::atom(c),
+        },
+        Value::Int(i) => Sexp::atom(i),
+        Value::Float(f) => Sexp::atom(float_atom(*f)),
+        Value::Option(None) => Sexp::List(vec![]),
+        Value::Option(Some(v)) => Sexp::List(vec![sexp_of(
+            v,
+            match rule {
+                Some(BinProtRule::Option(r)) => Some(r),
+                _ => None,
+            },
+        )]),
+        Value::Record(fields) => Sexp::List(
+            fields
+                .iter()
+                .map(|(k, v)| {
+                    let field_rule = match rule {
+                        Some(BinProtRule::Record(rules)) => rules
+                            .iter()
+                            .find(|f| f.field_name == *k)
+                            .map(|f| &f.field_rule),
+                        _ => None,
+                    };
+                    Sexp::List(vec![Sexp::atom(k), sexp_of(v, field_rule)])
+                })
+                .collect(),
+        ),
+        Value::Tuple(items) => Sexp::List(sexp_of_items(
+            items.iter(),
+            match rule {
+                Some(BinProtRule::Tuple(rules)) => rules,
+                _ => &[],
+            },
+        )),
+        Value::List(items) => Sexp::List(
+            items
+                .iter()
+                .map(|v| match rule {
+                    Some(BinProtRule::List(r)) => sexp_of(v, Some(r)),
+                    _ => sexp_of(v, None),
+                })
+                .collect(),
+        ),
+        Value::Sum { name, value, .. } => {
+            let summand = match rule {
+                Some(BinProtRule::Sum(summands)) => summands.iter().find(|s| s.ctor_name == *name),
+                _ => None,
+            };
+            let arity = summand.map(|s| s.ctor_args.len());
+            let args: Vec<&Value> = match (value.as_deref(), arity) {
+                (None, _) => vec![],
+                (Some(Value::Tuple(args)), arity) if arity != Some(1) => args.iter().collect(),
+                (Some(v), _) => vec![v],
+            };
+            if args.is_empty() {
+                return Sexp::atom(name);
+            }
+            let rules = summand.map(|s| &s.ctor_args[..]).unwrap_or_default();
+            let args = sexp_of_items(args.into_iter(), rules);
+            Sexp::List(std::iter::once(Sexp::atom(name)).chain(args).collect())
+        }
+    }
+}
+
+fn sexp_of_items<'v>(items: impl Iterator<Item = &'v Value>, rules: &[BinProtRule]) -> Vec<Sexp> {
+    items
+        .enumerate()
+        .map(|(i, v)| sexp_of(v, rules.get(i)))
+        .collect()
+}
+
+// OCaml's `sexp_of_float`: the shorter of "%.15G" and "%.17G" that reads back as the same float,
+// with a trailing "." if it would otherwise read as an integer
+fn float_atom(f: f64) -> String {
+    if f.is_nan() {
+        return "NAN".to_string();
+    }
+    if f.is_infinite() {
+        return if f > 0.0 { "INF" } else { "-INF" }.to_string();
+    }
+    let short = format_g(f, 15);
+    let s = if short.parse() == Ok(f) {
+        short
+    } else {
+        format_g(f, 17)
+    };
+    if s.bytes().all(|b| b.is_ascii_digit() || b == b'-') {
+        s + "."
+    } else {
+        s
+    }
+}
+
+// C's "%.<precision>G" for a finite float
+fn format_g(f: f64, precision: usize) -> String {
+    let exponential = format!("{:.*e}", precision - 1, f);
+    let (mantissa, exponent) = exponential.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let trim = |s: &str| -> String {
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.').to_string()
+        } else {
+            s.to_string()
+        }
+    };
+    if exponent < -4 || exponent >= precision as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}E{}{:02}", trim(mantissa), sign, exponent.abs())
+    } else {
+        trim(&format!(
+            "{:.*}",
+            (precision as i32 - 1 - exponent) as usize,
+            f
+        ))
+    }
+}
+
+struct SexpReader {
+    path: Vec<String>, // path to the current node, used for error reporting
+    module_path: ModulePath,
+}
+
+impl Walker for SexpReader {
+    fn module_path(&mut self) -> &mut ModulePath {
+        &mut self.module_path
+    }
+}
+
+impl SexpReader {
+    fn read(&mut self, sexp: &Sexp, rule: &BinProtRule) -> Result<Value> {
+        match rule {
+            BinProtRule::Unit => match sexp {
+                Sexp::List(l) if l.is_empty() => Ok(Value::Unit),
+                s => Err(self.invalid("()", s)),
+            },
+            BinProtRule::Bool => match self.atom(sexp, "a bool")? {
+                b"true" => Ok(Value::Bool(true)),
+                b"false" => Ok(Value::Bool(false)),
+                _ => Err(self.invalid("a bool", sexp)),
+            },
+            BinProtRule::Int | BinProtRule::Int64 | BinProtRule::NativeInt => {
+                self.parse_atom(sexp, "an integer").map(Value::Int)
+            }
+            BinProtRule::Int32 => self
+                .parse_atom::<i32>(sexp, "an Int32")
+                .map(|i| Value::Int(i.into())),
+            BinProtRule::Nat0 => self.parse_atom(sexp, "a nat0").map(Value::Nat0),
+            BinProtRule::Float => self.parse_atom(sexp, "a float").map(Value::Float),
+            // an OCaml char is a single byte
+            BinProtRule::Char => match self.atom(sexp, "a char")? {
+                [c] => Ok(Value::Char(char::from(*c))),
+                _ => Err(self.invalid("a char", sexp)),
+            },
+            BinProtRule::String => self
+                .atom(sexp, "a string")
+                .map(|s| Value::String(s.to_vec())),
+            BinProtRule::Option(r) => match self.list(sexp, "an option")? {
+                [] => Ok(Value::Option(None)),
+                [x] => Ok(Value::Option(Some(Box::new(self.read(x, r)?)))),
+                _ => Err(self.invalid("an option", sexp)),
+            },
+            BinProtRule::Record(fields) => {
+                let entries = self.list(sexp, "a record")?;
+                for entry in entries {
+                    let name = match entry {
+                        Sexp::List(kv) => match kv.as_slice() {
+                            [Sexp::Atom(k), _] => k,
+                            _ => return Err(self.invalid("a record field", entry)),
+                        },
+                        _ => return Err(self.invalid("a record field", entry)),
+                    };
+                    if !fields
+                        .iter()
+                        .any(|f| f.field_name.as_bytes() == name.as_slice())
+                    {
+                        return Err(Error::UnknownField {
+                            field: String::from_utf8_lossy(name).into_owned(),
+                            path: self.path(),
+                        });
+                    }
+                }
+                let mut values = Vec::with_capacity(fields.len());
+                for field in fields.iter() {
+                    let value = entries
+                        .iter()
+                        .find_map(|e| match e {
+                            Sexp::List(kv) => match kv.as_slice() {
+                                [Sexp::Atom(k), v] if *k == field.field_name.as_bytes() => Some(v),
+                                _ => None,
+                            },
+                            _ => None,
+                        })
+                        .ok_or_else(|| Error::MissingField {
+                            field: field.field_name.to_string(),
+                            path: self.path(),
+                        })?;
+                    let value = self.read_child(&field.field_name, value, &field.field_rule)?;
+                    values.push((field.field_name.clone(), value));
+                }
+                Ok(Value::Record(values))
+            }
+            BinProtRule::Tuple(rules) => match self.list(sexp, "a tuple")? {
+                items if items.len() == rules.len() => {
+                    self.read_items(items, rules.iter()).map(Value::Tuple)
+                }
+                _ => Err(self.invalid(&format!("a tuple of length {}", rules.len()), sexp)),
+            },
+            BinProtRule::List(r) => {
+                let items = self.list(sexp, "a list")?;
+                self.read_items(items, std::iter::repeat(&**r))
+                    .map(Value::List)
+            }
+            BinProtRule::Sum(summands) => self.read_sum(sexp, summands),
+            BinProtRule::Reference(RuleRef::Resolved(payload)) => {
+                self.within_reference(payload, |s, r| s.read(sexp, r))
+            }
+            BinProtRule::Custom(rules) => {
+                let (len, element) = self.module_path.custom_vector(rules)?;
+                match self.list(sexp, "a vector")? {
+                    items if items.len() == len => self
+                        .read_items(items, std::iter::repeat(element))
+                        .map(Value::List),
+                    _ => Err(self.invalid(&format!("a vector of length {}", len), sexp)),
+                }
+            }
+            r => Err(Error::Custom {
+                message: format!("No strategy to read s-expression for {:?}", r),
+            }),
+        }
+    }
+
+    // Constant constructors are a bare atom. Constructors with arguments are a list
+    // starting with the constructor name
+    fn read_sum(&mut self, sexp: &Sexp, summands: &[Summand]) -> Result<Value> {
+        let (name, args) = match sexp {
+            Sexp::Atom(name) => (name, &[][..]),
+            Sexp::List(items) => match items.split_first() {
+                Some((Sexp::Atom(name), args)) => (name, args),
+                _ => return Err(self.invalid("a constructor", sexp)),
+            },
+        };
+        let (index, summand) =
+            summand_index(summands, name).ok_or_else(|| Error::UnknownConstructor {
+                name: String::from_utf8_lossy(name).into_owned(),
+                path: self.path(),
+            })?;
+        if args.len() != summand.ctor_args.len() {
+            return Err(self.invalid(
+                &format!(
+                    "{} arguments to {}",
+                    summand.ctor_args.len(),
+                    summand.ctor_name
+                ),
+                sexp,
+            ));
+        }
+        self.path.push(summand.ctor_name.to_string());
+        let args = self.read_items(args, summand.ctor_args.iter());
+        self.path.pop();
+        Ok(Value::Sum {
+            name: summand.ctor_name.clone(),
+            index,
+            value: sum_payload(args?),
+        })
+    }
+
+    fn read_items<'r>(
+        &mut self,
+        items: &[Sexp],
+        rules: impl Iterator<Item = &'r BinProtRule>,
+    ) -> Result<Vec<Value>> {
+        items
+            .iter()
+            .zip(rules)
+            .enumerate()
+            .map(|(i, (item, rule))| self.read_child(&i.to_string(), item, rule))
+            .collect()
+    }
+
+    fn read_child(&mut self, segment: &str, sexp: &Sexp, rule: &BinProtRule) -> Result<Value> {
+        self.path.push(segment.to_string());
+        let result = self.read(sexp, rule);
+        self.path.pop();
+        result
+    }
+
+    fn atom<'s>(&self, sexp: &'s Sexp, expected: &str) -> Result<&'s [u8]> {
+        match sexp {
+            Sexp::Atom(s) => Ok(s),
+            s => Err(self.invalid(expected, s)),
+        }
+    }
+
+    fn parse_atom<T: std::str::FromStr>(&self, sexp: &Sexp, expected: &str) -> Result<T> {
+        std::str::from_utf8(self.atom(sexp, expected)?)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| self.invalid(expected, sexp))
+    }
+
+    fn list<'s>(&self, sexp: &'s Sexp, expected: &str) -> Result<&'s [Sexp]> {
+        match sexp {
+            Sexp::List(items) => Ok(items),
+            s => Err(self.invalid(expected, s)),
+        }
+    }
+
+    fn invalid(&self, expected: &str, found: &Sexp) -> Error {
+        Error::InvalidSexp {
+            expected: expected.to_string(),
+            found: found.to_string(),
+            path: self.path(),
+        }
+    }
+
+    // path to the current node in JSON Pointer syntax (see Value::pointer)
+    fn path(&self) -> String {
+        self.path.iter().map(|s| format!("/{}", s)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULE: &str = r#"
+    [
+      "Record",
+      [
+        { "field_name": "first", "field_rule": ["Int"] },
+        { "field_name": "second", "field_rule": ["Option", ["Sum", [
+          { "ctor_name": "One", "index": 0, "ctor_args": [] },
+          { "ctor_name": "Two", "index": 1, "ctor_args": [["Bool"], ["String"]] }
+        ]]] },
+        { "field_name": "third", "field_rule": ["List", ["Tuple", [["Char"], ["Unit"]]]] }
+      ]
+    ]
+    "#;
+
+    fn example() -> Value {
+        Value::Record(vec![
+            ("first".into(), Value::Int(-5)),
+            (
+                "second".into(),
+                Value::Option(Some(Box::new(Value::Sum {
+                    name: "Two".into(),
+                    index: 1,
+                    value: Some(Box::new(Value::Tuple(vec![
+                        Value::Bool(true),
+                        Value::String(b"a b".to_vec()),
+                    ]))),
+                }))),
+            ),
+            (
+                "third".into(),
+                Value::List(vec![Value::Tuple(vec![Value::Char('x'), Value::Unit])]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn value_to_sexp_text() {
+        assert_eq!(
+            example().to_sexp().to_string(),
+            r#"((first -5) (second ((Two true "a b"))) (third ((x ()))))"#
+        );
+    }
+
+    // expected output is from `Sexp.to_string_mach` of the equivalent `sexp_of_t` in OCaml
+    #[test]
+    fn matches_ocaml_output() {
+        let floats = [1.0, 1.5, 0.1, -0.0, 1e20, 1e-5, 1.0 / 3.0];
+        let floats = Value::List(floats.iter().map(|f| Value::Float(*f)).collect());
+        let text = "(1. 1.5 0.1 -0. 1E+20 1E-05 0.33333333333333331)";
+        assert_eq!(floats.to_sexp().to_string(), text);
+        let rule: BinProtRule = serde_json::from_str(r#"["List", ["Float"]]"#).unwrap();
+        assert_eq!(
+            Value::from_sexp(&text.parse().unwrap(), &rule).unwrap(),
+            floats
+        );
+        let strings = Value::Tuple(vec![
+            Value::String(vec![0xff, 0x01]),
+            Value::String("é".as_bytes().to_vec()),
+            Value::Char('\u{e9}'),
+        ]);
+        assert_eq!(
+            strings.to_sexp().to_string(),
+            r#"("\255\001" "\195\169" "\233")"#
+        );
+    }
+
+    #[test]
+    fn constructor_arguments_follow_the_layout() {
+        let rule: BinProtRule = serde_json::from_str(
+            r#"["List", ["Sum", [
+              { "ctor_name": "Unit", "index": 0, "ctor_args": [["Unit"]] },
+              { "ctor_name": "Pair", "index": 1, "ctor_args": [["Tuple", [["Int"], ["Int"]]]] },
+              { "ctor_name": "Constant", "index": 2, "ctor_args": [] }
+            ]]]"#,
+        )
+        .unwrap();
+        let value = Value::List(vec![
+            Value::sum("Unit", 0, vec![Value::Unit]),
+            Value::sum(
+                "Pair",
+                1,
+                vec![Value::Tuple(vec![Value::Int(1), Value::Int(2)])],
+            ),
+            Value::sum("Constant", 2, vec![]),
+        ]);
+        let text = "((Unit ()) (Pair (1 2)) Constant)";
+        assert_eq!(value.to_sexp_with_layout(&rule).to_string(), text);
+        assert_eq!(
+            Value::from_sexp(&text.parse().unwrap(), &rule).unwrap(),
+            value
+        );
+    }
+
+    #[test]
+    fn sexp_text_to_value() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let sexp: Sexp = r#"((first -5) (second ((Two true "a b"))) (third ((x ()))))"#
+            .parse()
+            .unwrap();
+        assert_eq!(Value::from_sexp(&sexp, &rule).unwrap(), example());
+
+        let sexp: Sexp = "((third ()) (second (One)) (first 0))".parse().unwrap();
+        assert_eq!(
+            Value::from_sexp(&sexp, &rule).unwrap()["second"].inner(),
+            Some(Value::Sum {
+                name: "One".into(),
+                index: 0,
+                value: None
+            })
+        );
+    }
+
+    #[test]
+    fn sexp_errors_have_paths() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let sexp: Sexp = "((first 1) (second ((Three))) (third ()))".parse().unwrap();
+        match Value::from_sexp(&sexp, &rule) {
+            Err(Error::UnknownConstructor { name, path }) => {
+                assert_eq!(name, "Three");
+                assert_eq!(path, "/second");
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        let sexp: Sexp = "((first x) (second ()) (third ()))".parse().unwrap();
+        assert!(matches!(
+            Value::from_sexp(&sexp, &rule),
+            Err(Error::InvalidSexp { path, .. }) if path == "/first"
+        ));
+    }
+
+    #[test]
+    fn sexp_vector_in_reference() {
+        let rule: BinProtRule = serde_json::from_str(
+            r#"["Reference", ["Resolved", {
+              "source_type_decl": "Pickles_types.Vector.Vector2.t",
+              "source_module_path": "Pickles_type.Vector.Vector2",
+              "ref_rule": ["Custom", [["Int"]]]
+            }]]"#,
+        )
+        .unwrap();
+        let sexp: Sexp = "(1 2)".parse().unwrap();
+        assert_eq!(
+            Value::from_sexp(&sexp, &rule).unwrap(),
+            Value::List(vec![Value::Int(1), Value::Int(2)])
+        );
+        let sexp: Sexp = "(1 2 3)".parse().unwrap();
+        assert!(matches!(
+            Value::from_sexp(&sexp, &rule),
+            Err(Error::InvalidSexp { expected, .. }) if expected == "a vector of length 2"
+        ));
+    }
+
+    #[test]
+    fn sexp_rejects_unknown_fields() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let sexp: Sexp = "((first 0) (second ()) (third ()) (fourth 1))"
+            .parse()
+            .unwrap();
+        match Value::from_sexp(&sexp, &rule) {
+            Err(Error::UnknownField { field, path }) => {
+                assert_eq!(field, "fourth");
+                assert_eq!(path, "");
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
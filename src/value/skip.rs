@@ -0,0 +1,186 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Skipping over encoded values without decoding them.
+//!
+//! `skip` follows a layout through the input the same way decoding does, checking that every
+//! byte is valid for its rule, but discards what it reads rather than building a Value.
+//! Strings are streamed past rather than buffered so skipping does not allocate per node.
+//! This makes it suitable for finding message boundaries in large files.
+
+use std::io::{self, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::error::{Error, Result};
+use crate::value::layout::{
+    read_vector_terminator, select_summand, BinProtRule, ModulePath, RuleRef, Walker,
+};
+use crate::ReadBinProtExt;
+
+/// Advance the reader past one value encoded with `rule`, validating it along the way.
+/// Returns the number of bytes the value occupied
+pub fn skip<R: Read>(rdr: R, rule: &BinProtRule) -> Result<usize> {
+    let mut skipper = Skipper::new(rdr);
+    skipper.skip(rule)?;
+    Ok(skipper.rdr.count)
+}
+
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    pub(crate) count: usize,
+}
+
+impl<R> CountingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+}
+
+pub(super) struct Skipper<R> {
+    pub(super) rdr: CountingReader<R>,
+    pub(super) module_path: ModulePath,
+}
+
+impl<R> Walker for Skipper<R> {
+    fn module_path(&mut self) -> &mut ModulePath {
+        &mut self.module_path
+    }
+}
+
+impl<R: Read> Skipper<R> {
+    pub(super) fn new(rdr: R) -> Self {
+        Self {
+            rdr: CountingReader::new(rdr),
+            module_path: ModulePath::default(),
+        }
+    }
+
+    pub(super) fn skip(&mut self, rule: &BinProtRule) -> Result<()> {
+        match rule {
+            BinProtRule::Unit => self.rdr.bin_read_unit()?,
+            BinProtRule::Bool => {
+                self.rdr.bin_read_bool()?;
+            }
+            BinProtRule::Char => {
+                self.rdr.read_u8()?;
+            }
+            BinProtRule::Int | BinProtRule::Int32 | BinProtRule::Int64 | BinProtRule::NativeInt => {
+                self.rdr.bin_read_integer::<i64>()?;
+            }
+            BinProtRule::Nat0 => {
+                self.rdr.bin_read_nat0::<u64>()?;
+            }
+            BinProtRule::Float => {
+                self.rdr.read_f64::<LittleEndian>()?;
+            }
+            BinProtRule::String => {
+                let len = self.rdr.bin_read_nat0::<u64>()?;
+                let skipped = io::copy(&mut (&mut self.rdr).take(len), &mut io::sink())?;
+                if skipped != len {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+            }
+            BinProtRule::Option(r) => {
+                if self.rdr.bin_read_bool()? {
+                    self.skip(r)?;
+                }
+            }
+            BinProtRule::Record(fields) => {
+                for field in fields.iter() {
+                    self.skip(&field.field_rule)?;
+                }
+            }
+            BinProtRule::Tuple(rules) => {
+                for r in rules.iter() {
+                    self.skip(r)?;
+                }
+            }
+            BinProtRule::List(r) => {
+                let len = self.rdr.bin_read_nat0::<u64>()?;
+                for _ in 0..len {
+                    self.skip(r)?;
+                }
+            }
+            BinProtRule::Sum(summands) => {
+                let summand = select_summand(summands, self.rdr.bin_read_variant_index()?)?;
+                for r in summand.ctor_args.iter() {
+                    self.skip(r)?;
+                }
+            }
+            BinProtRule::Reference(RuleRef::Resolved(payload)) => {
+                self.within_reference(payload, |s, r| s.skip(r))?
+            }
+            BinProtRule::Custom(rules) => {
+                let (len, element) = self.module_path.custom_vector(rules)?;
+                for _ in 0..len {
+                    self.skip(element)?;
+                }
+                read_vector_terminator(&mut self.rdr)?;
+            }
+            r => {
+                return Err(Error::Custom {
+                    message: format!("No strategy to skip {:?}", r),
+                })
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULE: &str = r#"
+    ["Record", [
+      { "field_name": "first", "field_rule": ["Option", ["Tuple", [["Int"], ["Bool"]]]] },
+      { "field_name": "second", "field_rule": ["Sum", [
+        { "ctor_name": "One", "index": 0, "ctor_args": [] },
+        { "ctor_name": "Two", "index": 1, "ctor_args": [["List", ["String"]]] }
+      ]] }
+    ]]
+    "#;
+
+    #[test]
+    fn finds_message_boundaries() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let messages: [&[u8]; 3] = [
+            &[0x01, 0xfe, 0x00, 0xff, 0x01, 0x00], // Some((-256, true)), One
+            &[0x00, 0x01, 0x02, 0x01, b'a', 0x02, b'b', b'c'], // None, Two(["a"; "bc"])
+            &[0x00, 0x00],                         // None, One
+        ];
+        let stream = messages.concat();
+
+        let mut rdr = stream.as_slice();
+        let lengths: Vec<usize> = (0..3).map(|_| skip(&mut rdr, &rule).unwrap()).collect();
+        assert_eq!(
+            lengths,
+            messages.iter().map(|m| m.len()).collect::<Vec<_>>()
+        );
+        assert!(rdr.is_empty());
+    }
+
+    #[test]
+    fn validates_while_skipping() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        // invalid bool
+        assert!(matches!(
+            skip([0x01, 0x05, 0x02].as_ref(), &rule),
+            Err(Error::InvalidByte { byte: 0x02, .. })
+        ));
+        // string longer than the input
+        assert!(matches!(
+            skip([0x00, 0x01, 0x01, 0x05, b'a'].as_ref(), &rule),
+            Err(Error::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,403 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Mapping the nodes of an encoded message to the bytes they occupy.
+//!
+//! Paths use the same syntax as `walk`. The contents of an option share the span of the option
+//! itself and the arguments of a constructor are under the constructor name, as a single node
+//! for one argument or as a tuple for several, e.g. `/second/Two/0`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+
+use super::skip::Skipper;
+use crate::error::{Error, Result};
+use crate::value::layout::{
+    read_vector_terminator, select_summand, BinProtRule, ModulePath, RuleRef, Walker,
+};
+use crate::ReadBinProtExt;
+
+/// The bytes `[start, end)` of a message occupied by the node at `path`
+#[derive(Clone, Debug)]
+pub struct Span<'r> {
+    pub path: String,
+    pub start: usize,
+    pub end: usize,
+    /// The rule the node was encoded with. None for the arguments of a constructor with
+    /// several, which have no rule of their own in the layout
+    pub rule: Option<&'r BinProtRule>,
+}
+
+impl Span<'_> {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// Reads one message using a layout and returns the span of every node, parents before
+/// their children
+pub fn spans<R: Read>(rdr: R, rule: &BinProtRule) -> Result<Vec<Span<'_>>> {
+    match partial_spans(rdr, rule) {
+        (spans, None) => Ok(spans),
+        (_, Some(failure)) => Err(failure.error),
+    }
+}
+
+/// Where reading a message failed
+pub(super) struct Failure {
+    /// Index of the innermost span being read
+    pub(super) span: usize,
+    /// Bytes read including any byte that was rejected
+    pub(super) offset: usize,
+    pub(super) error: Error,
+}
+
+/// As `spans` but on failure also returns the spans read so far. Spans that were not finished
+/// end at the failure offset
+pub(super) fn partial_spans<R: Read>(
+    rdr: R,
+    rule: &BinProtRule,
+) -> (Vec<Span<'_>>, Option<Failure>) {
+    let mut spanner = Spanner {
+        skipper: Skipper::new(rdr),
+        path: String::new(),
+        spans: Vec::new(),
+        failed: None,
+    };
+    let failure = spanner.visit(rule).err().map(|error| Failure {
+        span: spanner.failed.unwrap_or_default(),
+        offset: spanner.offset(),
+        error,
+    });
+    (spanner.spans, failure)
+}
+
+struct Spanner<'r, R> {
+    skipper: Skipper<R>,
+    path: String,
+    spans: Vec<Span<'r>>,
+    failed: Option<usize>, // the innermost span when an error occurred
+}
+
+impl<R> Walker for Spanner<'_, R> {
+    fn module_path(&mut self) -> &mut ModulePath {
+        &mut self.skipper.module_path
+    }
+}
+
+impl<'r, R: Read> Spanner<'r, R> {
+    fn offset(&self) -> usize {
+        self.skipper.rdr.count
+    }
+
+    fn visit(&mut self, rule: &'r BinProtRule) -> Result<()> {
+        self.node(Some(rule), |s| s.visit_contents(rule))
+    }
+
+    // adds the span of a node whose contents are read by `f`
+    fn node(
+        &mut self,
+        rule: Option<&'r BinProtRule>,
+        f: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let index = self.spans.len();
+        let start = self.offset();
+        self.spans.push(Span {
+            path: self.path.clone(),
+            start,
+            end: start,
+            rule,
+        });
+        let result = f(self);
+        self.spans[index].end = self.offset();
+        if result.is_err() && self.failed.is_none() {
+            self.failed = Some(index);
+        }
+        result
+    }
+
+    fn visit_child(&mut self, segment: &str, rule: &'r BinProtRule) -> Result<()> {
+        self.within_child(segment, |s| s.visit(rule))
+    }
+
+    fn within_child(
+        &mut self,
+        segment: &str,
+        f: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let len = self.path.len();
+        self.path.push('/');
+        self.path.push_str(segment);
+        let result = f(self);
+        self.path.truncate(len);
+        result
+    }
+
+    fn visit_contents(&mut self, rule: &'r BinProtRule) -> Result<()> {
+        match rule {
+            BinProtRule::Reference(RuleRef::Resolved(payload)) => {
+                self.within_reference(payload, |s, r| s.visit_contents(r))?
+            }
+            BinProtRule::Option(r) => {
+                if self.skipper.rdr.bin_read_bool()? {
+                    self.visit_contents(r)?;
+                }
+            }
+            BinProtRule::Record(fields) => {
+                for field in fields.iter() {
+                    self.visit_child(&field.field_name, &field.field_rule)?;
+                }
+            }
+            BinProtRule::Tuple(rules) => {
+                for (i, r) in rules.iter().enumerate() {
+                    self.visit_child(&i.to_string(), r)?;
+                }
+            }
+            BinProtRule::List(r) => {
+                let len = self.skipper.rdr.bin_read_nat0::<u64>()?;
+                for i in 0..len {
+                    self.visit_child(&i.to_string(), r)?;
+                }
+            }
+            BinProtRule::Sum(summands) => {
+                let byte = self.skipper.rdr.bin_read_variant_index()?;
+                let summand = select_summand(summands, byte)?;
+                // arguments are under the constructor name, as in Value::sum
+                match &summand.ctor_args[..] {
+                    [] => {}
+                    [arg] => self.visit_child(&summand.ctor_name, arg)?,
+                    args => self.within_child(&summand.ctor_name, |s| s.visit_arguments(args))?,
+                }
+            }
+            BinProtRule::Custom(rules) => {
+                let (len, element) = self.skipper.module_path.custom_vector(rules)?;
+                for i in 0..len {
+                    self.visit_child(&i.to_string(), element)?;
+                }
+                read_vector_terminator(&mut self.skipper.rdr)?;
+            }
+            r => self.skipper.skip(r)?,
+        }
+        Ok(())
+    }
+
+    // the arguments of a constructor with several are a node with a child for each
+    fn visit_arguments(&mut self, args: &'r [BinProtRule]) -> Result<()> {
+        self.node(None, |s| {
+            for (i, r) in args.iter().enumerate() {
+                s.visit_child(&i.to_string(), r)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Sizes of the node at a path across many messages
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SizeStats {
+    pub count: usize,
+    pub total: usize,
+    pub min: usize,
+    pub max: usize,
+}
+
+/// Aggregated node sizes by path over many messages.
+/// List indices in paths are replaced by `*` so the elements of a list are reported together
+/// e.g. `/entries/*/key`, the list node itself gives the total for all of its elements
+#[derive(Clone, Debug, Default)]
+pub struct SizeReport {
+    pub messages: usize,
+    pub paths: BTreeMap<String, SizeStats>,
+}
+
+impl SizeReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the spans of one message
+    pub fn add(&mut self, spans: &[Span]) {
+        self.messages += 1;
+        // the path, report path and whether it is a list of each ancestor of the current span
+        let mut ancestors: Vec<(&str, String, bool)> = Vec::new();
+        for span in spans {
+            while let Some((path, _, _)) = ancestors.last() {
+                let rest = span.path.strip_prefix(path);
+                if rest.is_some_and(|rest| rest.starts_with('/')) {
+                    break;
+                }
+                ancestors.pop();
+            }
+            let report_path = match ancestors.last() {
+                Some((_, report_path, true)) => format!("{}/*", report_path),
+                Some((path, report_path, _)) => {
+                    format!("{}{}", report_path, &span.path[path.len()..])
+                }
+                None => span.path.clone(),
+            };
+            ancestors.push((
+                &span.path,
+                report_path.clone(),
+                span.rule.is_some_and(is_list),
+            ));
+
+            let len = span.len();
+            let stats = self.paths.entry(report_path).or_insert(SizeStats {
+                count: 0,
+                total: 0,
+                min: len,
+                max: len,
+            });
+            stats.count += 1;
+            stats.total += len;
+            stats.min = stats.min.min(len);
+            stats.max = stats.max.max(len);
+        }
+    }
+}
+
+// the contents of an option are the same node as the option (see Spanner::visit_contents)
+fn is_list(rule: &BinProtRule) -> bool {
+    match rule {
+        BinProtRule::Reference(RuleRef::Resolved(payload)) => is_list(&payload.ref_rule),
+        BinProtRule::Option(r) => is_list(r),
+        BinProtRule::List(_) | BinProtRule::Custom(_) => true,
+        _ => false,
+    }
+}
+
+/// Renders a table of paths, largest total size first
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rows: Vec<_> = self.paths.iter().collect();
+        rows.sort_by(|(a_path, a), (b_path, b)| b.total.cmp(&a.total).then(a_path.cmp(b_path)));
+        writeln!(
+            f,
+            "{:>12} {:>8} {:>8} {:>8}  path ({} messages)",
+            "total", "count", "min", "max", self.messages
+        )?;
+        for (path, stats) in rows {
+            let path = if path.is_empty() { "/" } else { path };
+            writeln!(
+                f,
+                "{:>12} {:>8} {:>8} {:>8}  {}",
+                stats.total, stats.count, stats.min, stats.max, path
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULE: &str = r#"
+    ["Record", [
+      { "field_name": "first", "field_rule": ["Option", ["Tuple", [["Int"], ["Bool"]]]] },
+      { "field_name": "second", "field_rule": ["Sum", [
+        { "ctor_name": "One", "index": 0, "ctor_args": [] },
+        { "ctor_name": "Two", "index": 1, "ctor_args": [["List", ["String"]], ["Float"]] }
+      ]] }
+    ]]
+    "#;
+
+    fn ranges<'s>(spans: &'s [Span<'_>]) -> Vec<(&'s str, usize, usize)> {
+        spans
+            .iter()
+            .map(|s| (s.path.as_str(), s.start, s.end))
+            .collect()
+    }
+
+    #[test]
+    fn spans_of_every_node() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let mut bytes = vec![0x01, 0xfe, 0x00, 0x01, 0x01]; // Some((256, true))
+        bytes.extend_from_slice(&[0x01, 0x01, 0x02, b'a', b'b']); // Two(["ab"], ..
+        bytes.extend_from_slice(&1.5_f64.to_le_bytes()); // .. 1.5)
+
+        let spans = spans(bytes.as_slice(), &rule).unwrap();
+        assert_eq!(
+            ranges(&spans),
+            vec![
+                ("", 0, 18),
+                ("/first", 0, 5),
+                ("/first/0", 1, 4),
+                ("/first/1", 4, 5),
+                ("/second", 5, 18),
+                ("/second/Two", 6, 18),
+                ("/second/Two/0", 6, 10),
+                ("/second/Two/0/0", 7, 10),
+                ("/second/Two/1", 10, 18),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_aggregates_messages() {
+        let rule: BinProtRule = serde_json::from_str(RULE).unwrap();
+        let mut report = SizeReport::new();
+        report.add(&spans([0x00, 0x00].as_ref(), &rule).unwrap());
+        report.add(&spans([0x01, 0x05, 0x00, 0x00].as_ref(), &rule).unwrap());
+
+        assert_eq!(report.messages, 2);
+        assert_eq!(
+            report.paths["/first"],
+            SizeStats {
+                count: 2,
+                total: 4,
+                min: 1,
+                max: 3
+            }
+        );
+        assert_eq!(report.paths["/first/0"].count, 1);
+
+        // None, Two(["a", "bc"], 1.5)
+        let mut bytes = vec![0x00, 0x01, 0x02, 0x01, b'a', 0x02, b'b', b'c'];
+        bytes.extend_from_slice(&1.5_f64.to_le_bytes());
+        report.add(&spans(bytes.as_slice(), &rule).unwrap());
+        assert_eq!(
+            report.paths["/second/Two/0/*"],
+            SizeStats {
+                count: 2,
+                total: 5,
+                min: 2,
+                max: 3
+            }
+        );
+        assert!(!report.paths.contains_key("/second/Two/0/0"));
+        assert!(report.to_string().lines().nth(1).unwrap().ends_with("  /"));
+    }
+
+    #[test]
+    fn report_aggregates_vector_elements() {
+        let rule: BinProtRule = serde_json::from_str(
+            r#"["Reference", ["Resolved", {
+              "source_type_decl": "Pickles_types.Vector.Vector2.t",
+              "source_module_path": "Pickles_type.Vector.Vector2",
+              "ref_rule": ["Custom", [["String"]]]
+            }]]"#,
+        )
+        .unwrap();
+        let bytes = [0x01, b'a', 0x02, b'b', b'c', 0x00];
+        let mut report = SizeReport::new();
+        report.add(&spans(bytes.as_ref(), &rule).unwrap());
+
+        assert_eq!(report.paths[""].total, 6);
+        assert_eq!(
+            report.paths["/*"],
+            SizeStats {
+                count: 2,
+                total: 5,
+                min: 2,
+                max: 3
+            }
+        );
+        assert!(!report.paths.contains_key("/0"));
+    }
+}
@@ -1,11 +1,12 @@
-use crate::loose_deserializer::EnumData;
-use crate::value::Value;
+use crate::value::intern::shared;
+use crate::value::{EnumData, Value};
+use serde::de::DeserializeSeed;
 use serde::de::MapAccess;
 use serde::de::SeqAccess;
 use serde::de::Visitor;
 use serde::de::{EnumAccess, VariantAccess};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::sync::Arc;
 
 pub struct ValueVisitor;
 
@@ -36,6 +37,11 @@ impl<'de> Visitor<'de> for ValueVisitor {
     //     Ok(Value::Int(value.into()))
     // }
 
+    #[inline]
+    fn visit_u32<E>(self, value: u32) -> Result<Value, E> {
+        Ok(Value::Nat0(value))
+    }
+
     #[inline]
     fn visit_f64<E>(self, value: f64) -> Result<Value, E> {
         Ok(Value::Float(value))
@@ -49,6 +55,11 @@ impl<'de> Visitor<'de> for ValueVisitor {
         self.visit_string(String::from(value))
     }
 
+    #[inline]
+    fn visit_string<E>(self, value: String) -> Result<Value, E> {
+        Ok(Value::String(value.into_bytes()))
+    }
+
     #[inline]
     fn visit_bytes<E>(self, value: &[u8]) -> Result<Value, E> {
         Ok(Value::String(value.to_vec()))
@@ -86,13 +97,26 @@ impl<'de> Visitor<'de> for ValueVisitor {
         Ok(Value::Tuple(vec))
     }
 
+    // lists read using a layout arrive as a newtype around their elements
+    // (see loose_deserializer.rs)
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        match Deserialize::deserialize(deserializer)? {
+            Value::Tuple(items) => Ok(Value::List(items)),
+            v => Ok(v),
+        }
+    }
+
     fn visit_map<V>(self, mut visitor: V) -> Result<Value, V::Error>
     where
         V: MapAccess<'de>,
     {
-        let mut values = HashMap::new();
-        while let Some((k, v)) = visitor.next_entry()? {
-            values.insert(k, v).unwrap(); // returns old value of replacing a key. This cannot happen here so can unwrap
+        // fields are pushed in the order they are read so the layout ordering is preserved
+        let mut values = Vec::with_capacity(visitor.size_hint().unwrap_or(0));
+        while let Some(k) = visitor.next_key_seed(FieldName)? {
+            values.push((k, visitor.next_value()?));
         }
         Ok(Value::Record(values))
     }
@@ -104,12 +128,48 @@ impl<'de> Visitor<'de> for ValueVisitor {
         let (payload, variant_access) = data.variant::<EnumData>()?;
 
         // payload must encode the index and name in a deserializer
-        // the variant access can be used to retrieve the correct content based on this
+        // the variant access can be used to retrieve the correct content based on this.
+        // Constructors with no arguments have no payload, a single argument is stored as is
+        // and multiple arguments are collected into a Tuple
+        let value = match payload.arity {
+            0 => {
+                variant_access.unit_variant()?;
+                None
+            }
+            1 => Some(variant_access.newtype_variant()?),
+            n => Some(variant_access.tuple_variant(n, ValueVisitor)?),
+        };
 
         Ok(Value::Sum {
-            name: payload.name,
+            name: shared(&payload.name),
             index: payload.index,
-            value: Box::new(variant_access.newtype_variant()?),
+            value: value.map(Box::new),
         })
     }
 }
+
+// Deserializes a record field name, sharing it with the layout if it came from one (see intern.rs)
+struct FieldName;
+
+impl<'de> DeserializeSeed<'de> for FieldName {
+    type Value = Arc<str>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Arc<str>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FieldName {
+    type Value = Arc<str>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a field name")
+    }
+
+    fn visit_str<E>(self, name: &str) -> Result<Arc<str>, E> {
+        Ok(shared(name))
+    }
+}
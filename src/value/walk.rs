@@ -0,0 +1,175 @@
+// Copyright 2020 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0
+
+//! Traversal over every node of a Value.
+//!
+//! Nodes are visited depth first with a parent visited before its children. Each node is given
+//! with its path using the same syntax as `diff`: JSON Pointer segments for record fields and
+//! list/tuple indices, and the constructor name when entering the payload of a Sum.
+//! The contents of an option has the same path as the option itself.
+
+use super::Value;
+
+/// Calls `f` with the path and value of every node in `value`, starting with `value` itself
+pub fn walk<F>(value: &Value, f: &mut F)
+where
+    F: FnMut(&str, &Value),
+{
+    walk_inner(value, &mut String::new(), f)
+}
+
+/// Calls `f` with the path and a mutable reference to every node in `value`.
+/// A node is visited before its children so if `f` replaces a node it is the children
+/// of the replacement that are visited next
+pub fn walk_mut<F>(value: &mut Value, f: &mut F)
+where
+    F: FnMut(&str, &mut Value),
+{
+    walk_mut_inner(value, &mut String::new(), f)
+}
+
+fn walk_inner<F>(value: &Value, path: &mut String, f: &mut F)
+where
+    F: FnMut(&str, &Value),
+{
+    f(path, value);
+    let len = path.len();
+    match value {
+        Value::Option(Some(inner)) => walk_inner(inner, path, f),
+        Value::Record(fields) => {
+            for (name, v) in fields {
+                push_segment(path, name);
+                walk_inner(v, path, f);
+                path.truncate(len);
+            }
+        }
+        Value::Tuple(items) | Value::List(items) => {
+            for (i, v) in items.iter().enumerate() {
+                push_segment(path, &i.to_string());
+                walk_inner(v, path, f);
+                path.truncate(len);
+            }
+        }
+        Value::Sum {
+            name,
+            value: Some(value),
+            ..
+        } => {
+            push_segment(path, name);
+            walk_inner(value, path, f);
+            path.truncate(len);
+        }
+        _ => {}
+    }
+}
+
+fn walk_mut_inner<F>(value: &mut Value, path: &mut String, f: &mut F)
+where
+    F: FnMut(&str, &mut Value),
+{
+    f(path, value);
+    let len = path.len();
+    match value {
+        Value::Option(Some(inner)) => walk_mut_inner(inner, path, f),
+        Value::Record(fields) => {
+            for (name, v) in fields {
+                push_segment(path, name);
+                walk_mut_inner(v, path, f);
+                path.truncate(len);
+            }
+        }
+        Value::Tuple(items) | Value::List(items) => {
+            for (i, v) in items.iter_mut().enumerate() {
+                push_segment(path, &i.to_string());
+                walk_mut_inner(v, path, f);
+                path.truncate(len);
+            }
+        }
+        Value::Sum {
+            name,
+            value: Some(value),
+            ..
+        } => {
+            push_segment(path, name);
+            walk_mut_inner(value, path, f);
+            path.truncate(len);
+        }
+        _ => {}
+    }
+}
+
+fn push_segment(path: &mut String, segment: &str) {
+    path.push('/');
+    path.push_str(segment);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Value {
+        Value::Record(vec![
+            ("a".into(), Value::String(b"secret".to_vec())),
+            (
+                "b".into(),
+                Value::Option(Some(Box::new(Value::List(vec![
+                    Value::Int(1),
+                    Value::Sum {
+                        name: "Key".into(),
+                        index: 0,
+                        value: Some(Box::new(Value::String(b"pk".to_vec()))),
+                    },
+                ])))),
+            ),
+        ])
+    }
+
+    #[test]
+    fn visits_every_node_with_path() {
+        let mut paths = Vec::new();
+        walk(&example(), &mut |path, _| paths.push(path.to_string()));
+        assert_eq!(
+            paths,
+            vec!["", "/a", "/b", "/b", "/b/0", "/b/1", "/b/1/Key"]
+        );
+    }
+
+    #[test]
+    fn collects_and_rewrites() {
+        let mut strings = Vec::new();
+        walk(&example(), &mut |path, v| {
+            if let Some(s) = v.as_str() {
+                strings.push((path.to_string(), s.to_string()))
+            }
+        });
+        assert_eq!(
+            strings,
+            vec![
+                ("/a".to_string(), "secret".to_string()),
+                ("/b/1/Key".to_string(), "pk".to_string())
+            ]
+        );
+        for (path, s) in &strings {
+            assert_eq!(example().pointer(path).and_then(Value::as_str), Some(&**s));
+        }
+
+        let mut value = example();
+        walk_mut(&mut value, &mut |_, v| {
+            if let Value::String(s) = v {
+                *s = b"<redacted>".to_vec()
+            }
+        });
+        assert_eq!(value["a"].as_str(), Some("<redacted>"));
+        assert_eq!(
+            value
+                .pointer("/b/1")
+                .unwrap()
+                .as_sum()
+                .unwrap()
+                .2
+                .unwrap()
+                .as_str(),
+            Some("<redacted>")
+        );
+    }
+}
@@ -58,7 +58,7 @@ pub trait WriteBinProtExt: io::Write {
                 }
                 _ => {
                     self.write_u8(CODE_INT64)?;
-                    self.write_i64::<LittleEndian>(n as i64).map(|_| 9)
+                    self.write_i64::<LittleEndian>(n).map(|_| 9)
                 }
             }
         }
@@ -85,7 +85,7 @@ pub trait WriteBinProtExt: io::Write {
             }
             _ => {
                 self.write_u8(CODE_INT64)?;
-                self.write_u64::<LittleEndian>(n as u64).map(|_| 9)
+                self.write_u64::<LittleEndian>(n).map(|_| 9)
             }
         }
     }
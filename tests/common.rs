@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use serde::{Deserialize, Serialize};
 use serde_bin_prot::{from_reader, to_writer};
 use std::fmt::Debug;
@@ -13,6 +13,7 @@ struct B {
     a: A,
 }
 
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 struct TestFieldAttrs {
     #[serde(with = "nat0")]
@@ -41,6 +42,7 @@ pub struct PublicKey {
     poly: CompressedPoly,
 }
 
+#[allow(clippy::new_without_default)]
 impl PublicKey {
     pub fn new() -> Self {
         PublicKey {
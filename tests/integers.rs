@@ -51,7 +51,7 @@ fn add_windows_around_points(TestCase { min, max }: TestCase, points: Points) ->
     fn add_between(a: i64, b: i64, mut acc: Points) -> Points {
         println!("adding between {} and {}", a, b);
         for i in a..b {
-            acc.insert(i.clone());
+            acc.insert(i);
         }
         acc
     }
@@ -1,8 +1,11 @@
 use serde::de::Deserialize;
 use serde_bin_prot::error::Result;
+use std::sync::Arc;
+use std::thread;
+
 use serde_bin_prot::value::layout::{BinProtRule, Layout};
-use serde_bin_prot::value::Value;
-use serde_bin_prot::Deserializer;
+use serde_bin_prot::value::{JsonStyle, Value};
+use serde_bin_prot::{bin_prot_value, json_to_bin_prot, to_writer, Deserializer};
 
 const SIMPLE_RULE: &str = r#"
 [
@@ -54,16 +57,42 @@ fn test_record_rule() {
     println!("{:?}", result);
     assert_eq!(
         result,
-        Value::Record(vec![
-            ("first".to_string(), Value::Int(0)),
-            (
-                "second".to_string(),
-                Value::Record(vec![("inner".to_string(), Value::Bool(true))])
-            )
-        ])
+        bin_prot_value!({ first: 0, second: { inner: true } })
     )
 }
 
+#[test]
+fn test_records_share_field_names() {
+    let rule: BinProtRule = serde_json::from_str(RECORD_RULE).unwrap();
+    let decode = |bytes: &[u8]| -> Value {
+        let mut de = Deserializer::from_reader_with_layout(bytes, rule.clone());
+        Deserialize::deserialize(&mut de).expect("Failed to deserialize")
+    };
+    let (a, b) = (decode(&[0x00, 0x01]), decode(&[0x05, 0x00]));
+    let name = |v: &Value| v.as_record().unwrap()[0].0.clone();
+    assert!(Arc::ptr_eq(&name(&a), &name(&b)));
+}
+
+const OPTION_FIELD_RULE: &str = r#"
+[
+  "Record",
+  [
+    { "field_name": "first", "field_rule": ["Option", ["String"]] },
+    { "field_name": "second", "field_rule": ["Int"] }
+  ]
+]
+"#;
+
+#[test]
+fn test_none_option_field_followed_by_field() {
+    let rule: BinProtRule = serde_json::from_str(OPTION_FIELD_RULE).unwrap();
+    let example = vec![0x00, 0x05];
+
+    let mut de = Deserializer::from_reader_with_layout(example.as_slice(), rule);
+    let result: Value = Deserialize::deserialize(&mut de).expect("Failed to deserialize");
+    assert_eq!(result, bin_prot_value!({ first: None, second: 5 }))
+}
+
 const SUM_RULE: &str = r#"
 [
   "Sum",
@@ -92,9 +121,54 @@ fn test_sum_rule() {
     assert_eq!(
         result,
         Value::Sum {
-            name: "two".to_string(),
+            name: "two".into(),
             index: 1,
-            value: Box::new(Value::Bool(false))
+            value: Some(Box::new(Value::Bool(false)))
+        }
+    )
+}
+
+const MULTI_ARG_SUM_RULE: &str = r#"
+[
+  "Sum",
+  [
+    {
+      "ctor_name": "none",
+      "index": 0,
+      "ctor_args": []
+    },
+    {
+      "ctor_name": "pair",
+      "index": 1,
+      "ctor_args": [["Int"], ["Bool"]]
+    }
+  ]
+]
+"#;
+
+#[test]
+fn test_multi_arg_sum_rule() {
+    let rule: BinProtRule = serde_json::from_str(MULTI_ARG_SUM_RULE).unwrap();
+    let example = vec![0x01, 0x05, 0x01]; // Pair(5, true)
+
+    let mut de = Deserializer::from_reader_with_layout(example.as_slice(), rule);
+    let result: Value = Deserialize::deserialize(&mut de).expect("Failed to deserialize");
+    assert_eq!(result, bin_prot_value!(pair#1(5, true)))
+}
+
+#[test]
+fn test_constant_constructor_sum_rule() {
+    let rule: BinProtRule = serde_json::from_str(MULTI_ARG_SUM_RULE).unwrap();
+    let example = vec![0x00]; // None
+
+    let mut de = Deserializer::from_reader_with_layout(example.as_slice(), rule);
+    let result: Value = Deserialize::deserialize(&mut de).expect("Failed to deserialize");
+    assert_eq!(
+        result,
+        Value::Sum {
+            name: "none".into(),
+            index: 0,
+            value: None
         }
     )
 }
@@ -128,13 +202,111 @@ fn test_nested_sum_rule() {
     assert_eq!(
         result,
         Value::Sum {
-            name: "one".to_string(),
+            name: "one".into(),
             index: 0,
-            value: Box::new(Value::Record(vec![("first".to_string(), Value::Int(5))]))
+            value: Some(Box::new(Value::Record(vec![(
+                "first".into(),
+                Value::Int(5)
+            )])))
         }
     )
 }
 
+const LIST_RULE: &str = r#"["List", ["Int"]]"#;
+
+#[test]
+fn test_list_rule() {
+    let rule: BinProtRule = serde_json::from_str(LIST_RULE).unwrap();
+    let example = vec![0x02, 0x05, 0x06]; // [5; 6]
+
+    let mut de = Deserializer::from_reader_with_layout(example.as_slice(), rule);
+    let result: Value = Deserialize::deserialize(&mut de).expect("Failed to deserialize");
+    assert_eq!(result, Value::List(vec![Value::Int(5), Value::Int(6)]))
+}
+
+const UNIT_ARG_SUM_RULE: &str = r#"
+[
+  "Sum",
+  [
+    {
+      "ctor_name": "U",
+      "index": 0,
+      "ctor_args": [["Unit"]]
+    },
+    {
+      "ctor_name": "C",
+      "index": 1,
+      "ctor_args": []
+    }
+  ]
+]
+"#;
+
+#[test]
+fn test_unit_arg_sum_rule() {
+    let rule: BinProtRule = serde_json::from_str(UNIT_ARG_SUM_RULE).unwrap();
+    let example = vec![0x00, 0x00]; // U ()
+
+    let mut de = Deserializer::from_reader_with_layout(example.as_slice(), rule.clone());
+    let result: Value = Deserialize::deserialize(&mut de).expect("Failed to deserialize");
+    assert_eq!(result, bin_prot_value!(U#0(())));
+    assert_eq!(
+        result.to_json_with(JsonStyle::Yojson),
+        serde_json::json!(["U", null])
+    );
+    assert_eq!(
+        result.to_json_with(JsonStyle::Plain),
+        serde_json::json!({ "U": null })
+    );
+    assert_eq!(result.to_sexp().to_string(), "(U ())");
+    assert_ne!(result, bin_prot_value!(U#0));
+
+    let decoded = serde_bin_prot::value::decode_value(example.as_slice(), &rule).unwrap();
+    assert_eq!(decoded, result);
+}
+
+// Messages that decode to a Value which encodes back to the same bytes
+const REENCODE_CASES: &[(&str, &[u8])] = &[
+    (SIMPLE_RULE, &[0x01, 0x00, 0x00]),
+    (RECORD_RULE, &[0x00, 0x01]),
+    (SUM_RULE, &[0x01, 0x00]),
+    (MULTI_ARG_SUM_RULE, &[0x01, 0x05, 0x01]),
+    (MULTI_ARG_SUM_RULE, &[0x00]),
+    (NESTED_SUM_RULE, &[0x00, 0x05]),
+    (UNIT_ARG_SUM_RULE, &[0x00, 0x00]),
+    (UNIT_ARG_SUM_RULE, &[0x01]),
+    (LIST_RULE, &[0x02, 0x05, 0x06]),
+];
+
+#[test]
+fn test_value_reencodes_to_same_bytes() {
+    for &(rule, example) in REENCODE_CASES {
+        let rule: BinProtRule = serde_json::from_str(rule).unwrap();
+        let mut de = Deserializer::from_reader_with_layout(example, rule);
+        let result: Value = Deserialize::deserialize(&mut de).expect("Failed to deserialize");
+
+        let mut output = Vec::new();
+        to_writer(&mut output, &result).expect("Failed to serialize");
+        assert_eq!(output, example);
+    }
+}
+
+#[test]
+fn test_yojson_reencodes_to_same_bytes() {
+    for &(rule, example) in REENCODE_CASES {
+        let rule: BinProtRule = serde_json::from_str(rule).unwrap();
+        let mut de = Deserializer::from_reader_with_layout(example, rule.clone());
+        let result: Value = Deserialize::deserialize(&mut de).expect("Failed to deserialize");
+
+        for style in [JsonStyle::Yojson, JsonStyle::Plain] {
+            let mut output = Vec::new();
+            json_to_bin_prot(&result.to_json_with(style), &rule, &mut output)
+                .expect("Failed to encode");
+            assert_eq!(output, example);
+        }
+    }
+}
+
 const BLOCK_LAYOUT: &str =
     std::include_str!("fixtures/external_transition_custom_args_compressed.json");
 const BLOCK_BYTES: &[u8] = std::include_bytes!("fixtures/block.bin");
@@ -150,3 +322,31 @@ fn smoke_test_deserialize_block() {
     let _result: Result<Value> = Deserialize::deserialize(&mut de);
     // This is failing at the moment so don't check the result
 }
+
+#[test]
+fn test_shared_layout_decodes_many_messages() {
+    let layout: Layout = serde_json::from_value(serde_json::json!({
+        "layout_loc": "<unknown>",
+        "version_opt": null,
+        "type_decl": "<unknown>",
+        "bin_io_derived": true,
+        "bin_prot_rule": serde_json::from_str::<serde_json::Value>(MULTI_ARG_SUM_RULE).unwrap()
+    }))
+    .unwrap();
+    let layout = Arc::new(layout);
+
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let layout = Arc::clone(&layout);
+            thread::spawn(move || {
+                let example = vec![0x01, i, 0x01]; // Pair(i, true)
+                let mut de = Deserializer::from_reader_with_layout_ref(example.as_slice(), &layout);
+                let result: Value = Deserialize::deserialize(&mut de).unwrap();
+                assert_eq!(result, bin_prot_value!(pair#1(i64::from(i), true)));
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
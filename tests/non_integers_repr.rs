@@ -35,7 +35,7 @@ fn test_char() {
 #[test]
 fn test_float() {
     bin_prot_test! {
-        0x3c 0xb0 00 00 00 00 00 00 -> 2.2204460492503131E-16,
+        0x3c 0xb0 00 00 00 00 00 00 -> 2.220446049250313E-16,
         0x7f 0xf0 00 00 00 00 00 00 -> f64::INFINITY,
         0x7f 0xef 0xff 0xff 0xff 0xff 0xff 0xff -> 1.7976931348623157E+308,
         0x7f 0xf0 00 00 00 00 00 00 -> f64::INFINITY,
@@ -89,22 +89,22 @@ fn test_option() {
 #[test]
 fn test_pair() {
     bin_prot_test! {
-        .. .. .. .. .. .. .. .. 0x00 0x00 -> ((0, 0)),
-        .. .. .. .. .. .. .. .. 0x01 0x01 -> ((1, 1)),
-        .. .. .. .. .. .. 0xff 0xff 0xff 0xff -> ((-1, -1)),
-        0x7f 0xff 0xff 0xff 0xfd 0x7f 0xff 0xff 0xff 0xfd -> ((2147483647, 2147483647)),
-        0x80 0x00 0x00 0x00 0xfd 0x80 0x00 0x00 0x00 0xfd -> ((-2147483648, -2147483648))
+        .. .. .. .. .. .. .. .. 0x00 0x00 -> (0, 0),
+        .. .. .. .. .. .. .. .. 0x01 0x01 -> (1, 1),
+        .. .. .. .. .. .. 0xff 0xff 0xff 0xff -> (-1, -1),
+        0x7f 0xff 0xff 0xff 0xfd 0x7f 0xff 0xff 0xff 0xfd -> (2147483647, 2147483647),
+        0x80 0x00 0x00 0x00 0xfd 0x80 0x00 0x00 0x00 0xfd -> (-2147483648, -2147483648)
     }
 }
 
 #[test]
 fn test_triple() {
     bin_prot_test! {
-        .. .. .. .. .. .. .. .. .. .. .. .. 0x00 0x00 0x00 -> ((0, 0, 0)),
-        .. .. .. .. .. .. .. .. .. .. .. .. 0x01 0x01 0x01 -> ((1, 1, 1)),
-        .. .. .. .. .. .. .. .. .. 0xff 0xff 0xff 0xff 0xff 0xff -> ((-1, -1, -1)),
-        0x7f 0xff 0xff 0xff 0xfd 0x7f 0xff 0xff 0xff 0xfd 0x7f 0xff 0xff 0xff 0xfd -> ((2147483647, 2147483647, 2147483647)),
-        0x80 0x00 0x00 0x00 0xfd 0x80 0x00 0x00 0x00 0xfd 0x80 0x00 0x00 0x00 0xfd -> ((-2147483648, -2147483648, -2147483648))
+        .. .. .. .. .. .. .. .. .. .. .. .. 0x00 0x00 0x00 -> (0, 0, 0),
+        .. .. .. .. .. .. .. .. .. .. .. .. 0x01 0x01 0x01 -> (1, 1, 1),
+        .. .. .. .. .. .. .. .. .. 0xff 0xff 0xff 0xff 0xff 0xff -> (-1, -1, -1),
+        0x7f 0xff 0xff 0xff 0xfd 0x7f 0xff 0xff 0xff 0xfd 0x7f 0xff 0xff 0xff 0xfd -> (2147483647, 2147483647, 2147483647),
+        0x80 0x00 0x00 0x00 0xfd 0x80 0x00 0x00 0x00 0xfd 0x80 0x00 0x00 0x00 0xfd -> (-2147483648, -2147483648, -2147483648)
     }
 }
 